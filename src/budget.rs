@@ -0,0 +1,262 @@
+//! Size and memory budgets that the output must fit in.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    error::SquishError,
+    inspect::{format_bytes, Report},
+};
+
+/// The limits that the output has to fit in. Each one is optional.
+#[derive(Debug, Default, Clone)]
+pub struct Budgets {
+    pub max_file_size: Option<ByteSize>,
+    pub max_gpu_memory: Option<ByteSize>,
+    pub max_texture_size: Option<u32>,
+}
+
+/// A number of bytes, which can be parsed from strings like `512`, `64KiB`,
+/// `1.5MB` or `2GiB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub usize);
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: f64 = number
+            .parse()
+            .with_context(|| format!("invalid size '{s}'"))?;
+
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1000,
+            "m" | "mb" => 1000 * 1000,
+            "g" | "gb" => 1000 * 1000 * 1000,
+            "kib" => 1 << 10,
+            "mib" => 1 << 20,
+            "gib" => 1 << 30,
+            _ => bail!("unknown unit in size '{s}', expected eg. B, KiB, MiB or GiB"),
+        };
+
+        Ok(ByteSize((number * multiplier as f64) as usize))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ByteSize {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let size = String::deserialize(deserializer)?;
+        size.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_bytes(self.0))
+    }
+}
+
+impl Budgets {
+    /// Checks the output against every budget, failing with a list of the
+    /// budgets that were exceeded and what's responsible.
+    pub fn check(&self, output: &Report, file_bytes: usize) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+
+        if let Some(max) = self.max_file_size {
+            if file_bytes > max.0 {
+                problems.push(format!(
+                    "output file is {}, over the budget of {max} (images {}, geometry {}, JSON {})",
+                    format_bytes(file_bytes),
+                    format_bytes(output.totals.images),
+                    format_bytes(output.totals.geometry),
+                    format_bytes(output.totals.json),
+                ));
+            }
+        }
+
+        if let Some(max) = self.max_gpu_memory {
+            let total: usize = output.images.iter().map(|i| i.gpu_bytes).sum();
+            if total > max.0 {
+                let mut images: Vec<_> = output.images.iter().collect();
+                images.sort_by_key(|i| std::cmp::Reverse(i.gpu_bytes));
+
+                let mut problem = format!(
+                    "textures need an estimated {} of GPU memory, over the budget of {max}. Largest textures:",
+                    format_bytes(total)
+                );
+                for image in images.iter().take(5) {
+                    problem.push_str(&format!(
+                        "\n    image #{}{} {}x{}: {}",
+                        image.index,
+                        image
+                            .name
+                            .as_ref()
+                            .map(|n| format!(" \"{n}\""))
+                            .unwrap_or_default(),
+                        image.width.unwrap_or_default(),
+                        image.height.unwrap_or_default(),
+                        format_bytes(image.gpu_bytes)
+                    ));
+                }
+                problems.push(problem);
+            }
+        }
+
+        if let Some(max) = self.max_texture_size {
+            for image in &output.images {
+                let (width, height) = (
+                    image.width.unwrap_or_default(),
+                    image.height.unwrap_or_default(),
+                );
+                if width > max || height > max {
+                    problems.push(format!(
+                        "image #{} is {width}x{height}, over the maximum texture size of {max}",
+                        image.index
+                    ));
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(SquishError::OverBudget { problems }.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Estimates how much GPU memory a texture takes up, including its mip chain.
+///
+/// Formats we don't know about, and images that aren't KTX2 (which will be
+/// decoded when they're loaded), are assumed to be uncompressed RGBA8. A
+/// level count of zero means the mip chain is generated at load time.
+pub fn estimate_gpu_bytes(
+    format: Option<ktx2::Format>,
+    width: u32,
+    height: u32,
+    levels: u32,
+) -> usize {
+    let (block_width, block_height, block_bytes) =
+        format.and_then(block_layout).unwrap_or((1, 1, 4));
+    let full_chain = 32 - width.max(height).max(1).leading_zeros();
+    let levels = if levels == 0 { full_chain } else { levels };
+
+    (0..levels)
+        .map(|level| {
+            let width = (width >> level).max(1);
+            let height = (height >> level).max(1);
+            let blocks_wide = (width + block_width - 1) / block_width;
+            let blocks_high = (height + block_height - 1) / block_height;
+            blocks_wide as usize * blocks_high as usize * block_bytes
+        })
+        .sum()
+}
+
+/// The block width, block height and bytes per block of a Vulkan format.
+pub fn block_layout(format: ktx2::Format) -> Option<(u32, u32, usize)> {
+    let layout = match format.0.get() {
+        // R8G8B8A8 and B8G8R8A8, in all their variants.
+        37..=50 => (1, 1, 4),
+        // BC1.
+        131..=134 => (4, 4, 8),
+        // BC2 and BC3.
+        135..=138 => (4, 4, 16),
+        // BC4.
+        139..=140 => (4, 4, 8),
+        // BC5, BC6H and BC7.
+        141..=146 => (4, 4, 16),
+        // ETC2 RGB and RGB A1.
+        147..=150 => (4, 4, 8),
+        // ETC2 RGBA.
+        151..=152 => (4, 4, 16),
+        // ASTC, which always uses 16 byte blocks. Each block size has a UNORM
+        // and an SRGB variant, in this order.
+        value @ 157..=184 => {
+            const BLOCK_SIZES: [(u32, u32); 14] = [
+                (4, 4),
+                (5, 4),
+                (5, 5),
+                (6, 5),
+                (6, 6),
+                (8, 5),
+                (8, 6),
+                (8, 8),
+                (10, 5),
+                (10, 6),
+                (10, 8),
+                (10, 10),
+                (12, 10),
+                (12, 12),
+            ];
+            let (width, height) = BLOCK_SIZES[(value as usize - 157) / 2];
+            (width, height, 16)
+        }
+        _ => return None,
+    };
+
+    Some(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!("512".parse::<ByteSize>().unwrap(), ByteSize(512));
+        assert_eq!("64KiB".parse::<ByteSize>().unwrap(), ByteSize(64 * 1024));
+        assert_eq!("1.5 MB".parse::<ByteSize>().unwrap(), ByteSize(1_500_000));
+        assert_eq!("2gib".parse::<ByteSize>().unwrap(), ByteSize(2 << 30));
+        assert!("lots".parse::<ByteSize>().is_err());
+        assert!("12 parsecs".parse::<ByteSize>().is_err());
+    }
+
+    #[test]
+    fn gpu_memory_estimates() {
+        // 256x256 RGBA8 with a full mip chain.
+        let rgba8 = estimate_gpu_bytes(Some(ktx2::Format::R8G8B8A8_SRGB), 256, 256, 9);
+        assert_eq!(
+            rgba8,
+            (0..9).map(|l| (256usize >> l).pow(2) * 4).sum::<usize>()
+        );
+        assert_eq!(estimate_gpu_bytes(None, 256, 256, 0), rgba8);
+
+        // 6x6 blocks don't divide 256 evenly, so partial blocks round up.
+        let astc = estimate_gpu_bytes(Some(ktx2::Format::ASTC_6x6_SRGB_BLOCK), 256, 256, 1);
+        assert_eq!(astc, 43 * 43 * 16);
+
+        let astc = estimate_gpu_bytes(Some(ktx2::Format::ASTC_4x4_UNORM_BLOCK), 8, 8, 4);
+        assert_eq!(astc, (4 + 1 + 1 + 1) * 16);
+    }
+
+    #[test]
+    fn over_budget() {
+        let input = open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let report = Report::new(&input).unwrap();
+
+        let roomy = Budgets {
+            max_file_size: Some(ByteSize(1 << 20)),
+            max_gpu_memory: Some(ByteSize(1 << 20)),
+            max_texture_size: Some(256),
+        };
+        roomy.check(&report, 6556).unwrap();
+
+        let tight = Budgets {
+            max_file_size: Some(ByteSize(1024)),
+            max_gpu_memory: Some(ByteSize(1024)),
+            max_texture_size: Some(128),
+        };
+        let error = tight.check(&report, 6556).unwrap_err().to_string();
+        assert!(error.contains("output file is 6.4 KiB"));
+        assert!(error.contains("GPU memory"));
+        assert!(error.contains("image #0 is 256x256"));
+    }
+}
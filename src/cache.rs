@@ -0,0 +1,732 @@
+//! The cache of compressed textures, and the `cache` subcommand for looking
+//! after it.
+//!
+//! Textures are stored by a hash of their source image and the settings they
+//! were compressed with. Every time one is used its access time is bumped,
+//! and once the cache is over its maximum size the least recently used ones
+//! are deleted.
+//!
+//! The cache can be shared by several machines, eg. on a network file
+//! system. Entries are written to a temporary file and renamed into place, so
+//! nobody sees one half written, and each starts with a manifest holding a
+//! checksum of the texture and how it was made:
+//!
+//! ```text
+//! squisher-cache 1
+//! {"checksum":"…","length":1234,"manifest":{"texture_type":"Normal",…}}
+//! <KTX2 file>
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hasher,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, ensure, Context};
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    budget::ByteSize,
+    config::{self, CacheSettings},
+    inspect::format_bytes,
+    quality::{self, Metrics},
+};
+
+/// How big the cache can get if nothing says otherwise.
+const DEFAULT_MAX_SIZE: ByteSize = ByteSize(2 << 30);
+
+/// The first line of every entry, changed whenever the format changes.
+const MAGIC: &[u8] = b"squisher-cache 1\n";
+
+/// The prefix of entries that are still being written.
+const TEMP_PREFIX: &str = ".tmp";
+
+/// How old a temporary file has to be before we assume whoever was writing
+/// it has crashed.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(clap::Args)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    command: CacheCommand,
+
+    /// Read the cache settings from this file instead of the squisher.toml
+    /// closest to the current directory.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Enables more verbose logging.
+    #[clap(short, long, global = true)]
+    verbose: bool,
+}
+
+#[derive(clap::Subcommand)]
+enum CacheCommand {
+    /// Shows where the cache is and how much is in it.
+    Stats,
+    /// Deletes every texture in the cache.
+    Clear,
+    /// Deletes corrupt textures, then the least recently used ones until the
+    /// cache fits in its maximum size.
+    Prune {
+        /// The size to shrink the cache to, instead of its maximum size.
+        #[clap(long)]
+        max_size: Option<ByteSize>,
+    },
+}
+
+pub fn cache(args: CacheArgs) -> anyhow::Result<()> {
+    crate::configure_logging(args.verbose);
+
+    let cache = Cache::new(&settings(args.config.as_deref())?)?;
+
+    match args.command {
+        CacheCommand::Stats => {
+            let stats = cache.stats()?;
+            println!("Cache:    {}", stats.dir.display());
+            println!("Textures: {}", stats.textures);
+            println!(
+                "Size:     {} of {}",
+                format_bytes(stats.bytes),
+                format_bytes(stats.max_bytes)
+            );
+        }
+        CacheCommand::Clear => {
+            let entries = cache.entries()?;
+            for entry in &entries {
+                fs_err::remove_file(&entry.path)?;
+            }
+            log::info!("Deleted {} textures", entries.len());
+        }
+        CacheCommand::Prune { max_size } => {
+            cache.remove_stale_temp_files()?;
+            let corrupt = cache.remove_corrupt()?;
+            let (count, size) = cache.evict(max_size.unwrap_or(cache.max_size))?;
+            log::info!(
+                "Deleted {corrupt} corrupt textures, and {count} more to free {}",
+                format_bytes(size)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The cache settings from a config, or the squisher.toml closest to the
+/// current directory.
+pub(crate) fn settings(config: Option<&Path>) -> anyhow::Result<CacheSettings> {
+    let config = match config {
+        Some(path) => Some(path.to_path_buf()),
+        None => config::find_in(&std::env::current_dir()?),
+    };
+    match config {
+        Some(path) => Ok(config::Config::load(&path)?.cache),
+        None => Ok(CacheSettings::default()),
+    }
+}
+
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    max_size: ByteSize,
+    /// What's in the directory, if it's being kept track of in memory.
+    index: Option<Index>,
+}
+
+/// The textures in a cache directory by path, listed the first time they're
+/// needed and then kept up to date as they're used, added and evicted.
+type Index = Arc<Mutex<Option<HashMap<PathBuf, Entry>>>>;
+
+/// The caches used by a process that squishes more than once, each with an
+/// index shared by everything using it, so that squishing doesn't list the
+/// whole directory every time. Textures other processes add to a cache aren't
+/// in its index until they're used.
+#[derive(Default)]
+pub struct Caches {
+    /// Each cache as it was last opened, by directory.
+    opened: Mutex<BTreeMap<PathBuf, Cache>>,
+}
+
+/// Where the cache is and how much is in it.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub dir: PathBuf,
+    pub textures: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+}
+
+/// How a cached texture was made, to help track down where a bad one came
+/// from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub texture_type: String,
+    pub format: String,
+    /// The ASTC block size, if it's ASTC.
+    pub block_size: Option<String>,
+    /// The quality target the block size was picked for, if it was picked
+    /// automatically.
+    pub quality_target: Option<f64>,
+    pub supercompression: bool,
+    pub max_size: u32,
+    /// The version `toktx --version` gave.
+    pub toktx: String,
+    /// How close the texture is to its source image, so it doesn't need
+    /// measuring again every time it's used.
+    #[serde(default)]
+    pub quality: Option<Metrics>,
+}
+
+/// The line before the texture in an entry.
+#[derive(Serialize, Deserialize)]
+struct EntryHeader {
+    /// The hash of the texture, as 16 hexadecimal digits.
+    checksum: String,
+    /// The length of the texture in bytes.
+    length: usize,
+    manifest: Manifest,
+}
+
+/// A texture in the cache.
+#[derive(Clone)]
+struct Entry {
+    path: PathBuf,
+    size: usize,
+    accessed: SystemTime,
+}
+
+impl Cache {
+    /// Works out where the cache is and how big it can get, from the
+    /// environment, the config and then the defaults.
+    pub(crate) fn new(settings: &CacheSettings) -> anyhow::Result<Self> {
+        let dir = std::env::var_os("SQUISHER_CACHE_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| settings.dir.clone())
+            .unwrap_or_else(default_dir);
+
+        let max_size = match std::env::var("SQUISHER_CACHE_MAX_SIZE") {
+            Ok(size) => size
+                .parse()
+                .context("invalid SQUISHER_CACHE_MAX_SIZE environment variable")?,
+            Err(_) => settings.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+        };
+
+        Ok(Self {
+            dir,
+            max_size,
+            index: None,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns a cached texture and its manifest, if it's there and intact.
+    /// Corrupt ones are deleted, so they get compressed again rather than
+    /// ending up in the output.
+    pub fn get(&self, name: &str) -> Option<(Vec<u8>, Manifest)> {
+        let path = self.dir.join(name);
+        let Ok(bytes) = fs_err::read(&path) else {
+            // Someone else might have evicted it.
+            self.update_index(|entries| entries.remove(&path));
+            return None;
+        };
+        let entry = match read_entry(&bytes) {
+            Ok((header, texture)) => (texture.to_vec(), header.manifest),
+            Err(e) => {
+                log::warn!(
+                    "Discarding corrupt cached texture {}: {e:#}",
+                    path.display()
+                );
+                let _ = fs_err::remove_file(&path);
+                self.update_index(|entries| entries.remove(&path));
+                return None;
+            }
+        };
+        self.update_index(|entries| {
+            let entry = Entry {
+                path: path.clone(),
+                size: bytes.len(),
+                accessed: SystemTime::now(),
+            };
+            entries.insert(path.clone(), entry)
+        });
+
+        // Access times aren't always kept up to date by the file system, so
+        // set them ourselves for eviction to go by.
+        if let Err(e) = filetime::set_file_atime(&path, FileTime::now()) {
+            log::debug!("Unable to update access time of {}: {e}", path.display());
+        }
+        Some(entry)
+    }
+
+    /// Adds a texture to the cache. Another process adding the same one at
+    /// the same time is fine, as whichever is renamed into place last wins.
+    pub fn put(&self, name: &str, texture: &[u8], manifest: &Manifest) -> anyhow::Result<()> {
+        let header = EntryHeader {
+            checksum: checksum(texture),
+            length: texture.len(),
+            manifest: manifest.clone(),
+        };
+
+        let mut file = tempfile::Builder::new()
+            .prefix(TEMP_PREFIX)
+            .tempfile_in(&self.dir)
+            .context("failed to create temporary file")?;
+        file.write_all(MAGIC)?;
+        gltf::json::serialize::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        file.write_all(texture)?;
+        file.as_file().sync_all()?;
+        let size = file.as_file().metadata()?.len() as usize;
+        let path = self.dir.join(name);
+        file.persist(&path)?;
+
+        self.update_index(|entries| {
+            let entry = Entry {
+                path: path.clone(),
+                size,
+                accessed: SystemTime::now(),
+            };
+            entries.insert(path.clone(), entry)
+        });
+        Ok(())
+    }
+
+    /// Deletes the least recently used textures until the cache fits in its
+    /// maximum size.
+    pub fn shrink(&self) -> anyhow::Result<()> {
+        // With an index, they're only looked for when it's first loaded.
+        if self.index.is_none() {
+            self.remove_stale_temp_files()?;
+        }
+        let (count, size) = self.evict(self.max_size)?;
+        if count > 0 {
+            log::info!(
+                "Evicted {count} textures ({}) from the cache to keep it under {}",
+                format_bytes(size),
+                self.max_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Counts the textures in the cache and how much space they take up.
+    pub fn stats(&self) -> anyhow::Result<Stats> {
+        let entries = self.entries()?;
+        Ok(Stats {
+            dir: self.dir.clone(),
+            textures: entries.len(),
+            bytes: entries.iter().map(|e| e.size).sum(),
+            max_bytes: self.max_size.0,
+        })
+    }
+
+    /// Deletes the least recently used textures until the cache is no bigger
+    /// than `max_size`, returning how many were deleted and their total size.
+    fn evict(&self, max_size: ByteSize) -> anyhow::Result<(usize, usize)> {
+        let mut entries = self.entries()?;
+        let mut total: usize = entries.iter().map(|e| e.size).sum();
+        entries.sort_by_key(|e| e.accessed);
+
+        let (mut count, mut freed) = (0, 0);
+        for entry in entries {
+            if total <= max_size.0 {
+                break;
+            }
+            remove_if_exists(&entry.path)?;
+            self.update_index(|entries| entries.remove(&entry.path));
+            total -= entry.size;
+            freed += entry.size;
+            count += 1;
+        }
+
+        Ok((count, freed))
+    }
+
+    /// Deletes every texture that's corrupt, returning how many there were.
+    fn remove_corrupt(&self) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for entry in self.entries()? {
+            // Someone else might have deleted it since we listed it.
+            let bytes = match fs_err::read(&entry.path) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if let Err(e) = read_entry(&bytes) {
+                log::info!("Deleting {}: {e:#}", entry.path.display());
+                remove_if_exists(&entry.path)?;
+                self.update_index(|entries| entries.remove(&entry.path));
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Deletes temporary files left behind by processes that crashed while
+    /// writing them.
+    fn remove_stale_temp_files(&self) -> anyhow::Result<()> {
+        let Ok(read_dir) = fs_err::read_dir(&self.dir) else {
+            return Ok(());
+        };
+
+        for dir_entry in read_dir {
+            let dir_entry = dir_entry?;
+            let is_temp = dir_entry
+                .file_name()
+                .to_str()
+                .map_or(false, |name| name.starts_with(TEMP_PREFIX));
+            let age = dir_entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if is_temp && age.map_or(false, |age| age > STALE_AFTER) {
+                log::debug!("Deleting stale {}", dir_entry.path().display());
+                remove_if_exists(&dir_entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the textures in the cache, from its index if it has one.
+    fn entries(&self) -> anyhow::Result<Vec<Entry>> {
+        let Some(index) = &self.index else {
+            return self.list();
+        };
+        let mut index = index.lock().unwrap();
+        if index.is_none() {
+            self.remove_stale_temp_files()?;
+            let entries = self.list()?;
+            *index = Some(entries.into_iter().map(|e| (e.path.clone(), e)).collect());
+        }
+        Ok(index
+            .iter()
+            .flat_map(|entries| entries.values().cloned())
+            .collect())
+    }
+
+    /// Records a change to the cache in its index, if it has one that's been
+    /// loaded.
+    fn update_index<T>(&self, update: impl FnOnce(&mut HashMap<PathBuf, Entry>) -> T) {
+        if let Some(index) = &self.index {
+            if let Some(entries) = index.lock().unwrap().as_mut() {
+                update(entries);
+            }
+        }
+    }
+
+    /// Lists the textures in the cache directory. Anything else in it is
+    /// left alone, in case it's been pointed somewhere shared.
+    fn list(&self) -> anyhow::Result<Vec<Entry>> {
+        let read_dir = match fs_err::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for dir_entry in read_dir {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name();
+            let is_texture = name.to_str().map_or(false, |name| {
+                name.len() == 16 && name.bytes().all(|b| b.is_ascii_hexdigit())
+            });
+            let metadata = dir_entry.metadata()?;
+            if !is_texture || !metadata.is_file() {
+                continue;
+            }
+
+            entries.push(Entry {
+                path: dir_entry.path(),
+                size: metadata.len() as usize,
+                accessed: metadata.accessed().or_else(|_| metadata.modified())?,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+impl Caches {
+    /// Opens the cache the settings describe, sharing its index with
+    /// everything else that's opened it.
+    pub(crate) fn open(&self, settings: &CacheSettings) -> anyhow::Result<Cache> {
+        let mut cache = Cache::new(settings)?;
+        let mut opened = self.opened.lock().unwrap();
+        let shared = opened
+            .entry(cache.dir.clone())
+            .or_insert_with(|| cache.clone());
+        cache.index = Some(shared.index.get_or_insert_with(Index::default).clone());
+        shared.max_size = cache.max_size;
+        Ok(cache)
+    }
+
+    /// The stats of every cache that's been opened, by directory.
+    pub fn stats(&self) -> anyhow::Result<Vec<Stats>> {
+        let opened = self.opened.lock().unwrap();
+        opened.values().map(Cache::stats).collect()
+    }
+}
+
+/// The cache directory for the platform, falling back to the temporary
+/// directory.
+fn default_dir() -> PathBuf {
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Caches"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home().map(|home| home.join(".cache")))
+    };
+
+    match base {
+        Some(base) => base.join("squisher"),
+        None => std::env::temp_dir().join("squisher-cache"),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> anyhow::Result<()> {
+    match fs_err::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    let mut hasher = seahash::SeaHasher::new();
+    hasher.write(bytes);
+    format!("{:016X}", hasher.finish())
+}
+
+/// Splits an entry into its header and texture, checking that the texture
+/// is the one the header describes and that it's a complete KTX2 file.
+fn read_entry(bytes: &[u8]) -> anyhow::Result<(EntryHeader, &[u8])> {
+    let rest = bytes
+        .strip_prefix(MAGIC)
+        .context("not a squisher cache entry, or one from another version")?;
+    let newline = rest
+        .iter()
+        .position(|&b| b == b'\n')
+        .context("entry header is truncated")?;
+    let header: EntryHeader =
+        gltf::json::deserialize::from_slice(&rest[..newline]).context("invalid entry header")?;
+
+    let texture = &rest[newline + 1..];
+    ensure!(
+        texture.len() == header.length,
+        "texture is {} bytes, expected {}",
+        texture.len(),
+        header.length
+    );
+    ensure!(
+        checksum(texture) == header.checksum,
+        "texture doesn't match its checksum"
+    );
+    check_ktx2(texture)?;
+
+    Ok((header, texture))
+}
+
+/// Checks that a KTX2 file is complete: that every level is within the file
+/// and, if they're supercompressed, that they decompress to the right size.
+fn check_ktx2(bytes: &[u8]) -> anyhow::Result<()> {
+    const HEADER_LENGTH: usize = 80;
+    const LEVEL_INDEX_LENGTH: usize = 24;
+
+    let reader = quality::read_ktx2(bytes)?;
+    let header = reader.header();
+
+    let levels = header.level_count.max(1) as usize;
+    for level in 0..levels {
+        let start = HEADER_LENGTH + level * LEVEL_INDEX_LENGTH;
+        let index = &bytes[start..start + LEVEL_INDEX_LENGTH];
+        let field = |i: usize| u64::from_le_bytes(index[i * 8..i * 8 + 8].try_into().unwrap());
+        let (offset, length, uncompressed_length) = (field(0), field(1), field(2));
+
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset as usize..end as usize))
+            .filter(|data| !data.is_empty())
+            .with_context(|| format!("level {level} is truncated"))?;
+
+        match header.supercompression_scheme {
+            None => {}
+            Some(ktx2::SupercompressionScheme::Zstandard) => {
+                let mut decoded = Vec::new();
+                ruzstd::StreamingDecoder::new(data)
+                    .map_err(|e| anyhow::anyhow!("{e}"))
+                    .and_then(|mut decoder| Ok(decoder.read_to_end(&mut decoded)?))
+                    .with_context(|| format!("level {level} has invalid Zstandard data"))?;
+                ensure!(
+                    decoded.len() as u64 == uncompressed_length,
+                    "level {level} decompresses to the wrong size"
+                );
+            }
+            Some(scheme) => bail!("unexpected supercompression scheme {scheme:?}"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality::tests::rgba8_ktx2;
+
+    fn test_cache(max_size: usize) -> (tempfile::TempDir, Cache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache {
+            dir: dir.path().to_path_buf(),
+            max_size: ByteSize(max_size),
+            index: None,
+        };
+        (dir, cache)
+    }
+
+    fn ktx2() -> Vec<u8> {
+        rgba8_ktx2(&image::RgbaImage::new(4, 4))
+    }
+
+    fn manifest() -> Manifest {
+        Manifest {
+            texture_type: "BaseColor".into(),
+            format: "Rgba8".into(),
+            block_size: None,
+            quality_target: None,
+            supercompression: false,
+            max_size: 4096,
+            toktx: "v4.1.0".into(),
+            quality: Some(Metrics {
+                psnr: f64::INFINITY,
+                ssim: 1.0,
+                max_error: 0,
+                normal_error: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let texture = ktx2();
+        let (_dir, cache) = test_cache(usize::MAX);
+
+        let names = ["000000000000000A", "000000000000000B", "000000000000000C"];
+        for (i, name) in names.iter().enumerate() {
+            cache.put(name, &texture, &manifest()).unwrap();
+            let accessed = FileTime::from_unix_time(1_000_000 + i as i64, 0);
+            filetime::set_file_atime(cache.dir.join(name), accessed).unwrap();
+        }
+        fs_err::write(cache.dir.join("notes.txt"), "not a texture").unwrap();
+
+        // Using the oldest one makes it the most recently used.
+        assert!(cache.get(names[0]).is_some());
+        let entry_size = fs_err::metadata(cache.dir.join(names[0])).unwrap().len();
+        let (count, _) = cache.evict(ByteSize(entry_size as usize * 2)).unwrap();
+        assert_eq!(count, 1);
+
+        assert!(cache.dir.join(names[0]).exists());
+        assert!(!cache.dir.join(names[1]).exists());
+        assert!(cache.dir.join(names[2]).exists());
+        assert!(cache.dir.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn discards_corrupt_textures() {
+        let texture = ktx2();
+        let (_dir, cache) = test_cache(usize::MAX);
+
+        // Truncated, like a write that was interrupted.
+        let path = cache.dir.join("000000000000000A");
+        cache
+            .put("000000000000000A", &texture, &manifest())
+            .unwrap();
+        let entry = fs_err::read(&path).unwrap();
+        fs_err::write(&path, &entry[..entry.len() - 1]).unwrap();
+        assert!(cache.get("000000000000000A").is_none());
+        assert!(!path.exists());
+
+        // A flipped bit.
+        let mut flipped = entry.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        fs_err::write(&path, flipped).unwrap();
+        assert!(cache.get("000000000000000A").is_none());
+
+        // A bare KTX2 file from an older version.
+        fs_err::write(cache.dir.join("000000000000000B"), &texture).unwrap();
+        cache
+            .put("000000000000000C", &texture, &manifest())
+            .unwrap();
+        assert_eq!(cache.remove_corrupt().unwrap(), 1);
+        assert_eq!(
+            cache.get("000000000000000C").unwrap(),
+            (texture, manifest())
+        );
+
+        let (header, _) = read_entry(&entry).unwrap();
+        assert_eq!(header.manifest, manifest());
+    }
+
+    #[test]
+    fn removes_stale_temp_files() {
+        let (_dir, cache) = test_cache(usize::MAX);
+        let stale = cache.dir.join(".tmpAbCdEf");
+        let fresh = cache.dir.join(".tmpGhIjKl");
+        fs_err::write(&stale, "half written").unwrap();
+        fs_err::write(&fresh, "being written").unwrap();
+        filetime::set_file_mtime(&stale, FileTime::from_unix_time(1_000_000, 0)).unwrap();
+
+        cache.remove_stale_temp_files().unwrap();
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+    }
+
+    #[test]
+    fn shares_index() {
+        let texture = ktx2();
+        let dir = tempfile::tempdir().unwrap();
+        let settings = CacheSettings {
+            dir: Some(dir.path().to_path_buf()),
+            max_size: Some(ByteSize(usize::MAX)),
+        };
+        let caches = Caches::default();
+
+        let first = caches.open(&settings).unwrap();
+        first
+            .put("000000000000000A", &texture, &manifest())
+            .unwrap();
+        assert_eq!(caches.stats().unwrap()[0].textures, 1);
+
+        // What's been put through one is seen by the other, without listing
+        // the directory again.
+        let second = caches.open(&settings).unwrap();
+        second
+            .put("000000000000000B", &texture, &manifest())
+            .unwrap();
+        fs_err::write(dir.path().join("000000000000000C"), "not seen").unwrap();
+        let stats = caches.stats().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].textures, 2);
+
+        let entry_size = fs_err::metadata(dir.path().join("000000000000000A"))
+            .unwrap()
+            .len();
+        let (count, _) = second.evict(ByteSize(entry_size as usize)).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(first.stats().unwrap().textures, 1);
+    }
+}
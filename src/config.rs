@@ -0,0 +1,249 @@
+//! Named profiles read from a `squisher.toml`, so the settings for each
+//! platform we target live in one place rather than in build scripts.
+//!
+//! ```toml
+//! [profiles.quest2]
+//! max-size = 2048
+//! auto-block-size = true
+//!
+//! [profiles.quest2.formats]
+//! normal = "rgba8"
+//!
+//! [profiles.quest2.block-sizes]
+//! base-color = "8x8"
+//!
+//! [profiles.quest2.quality]
+//! base-color = 36
+//!
+//! [cache]
+//! dir = "target/squisher-cache"
+//! max-size = "4GiB"
+//! ```
+//!
+//! It can also say which `toktx` to use, with a top level
+//! `toktx = "tools/toktx"`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{budget::ByteSize, TextureFormat, TextureType, BLOCK_SIZES};
+
+pub const FILE_NAME: &str = "squisher.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    #[serde(default)]
+    pub(crate) profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub(crate) cache: CacheSettings,
+    /// The `toktx` to use, relative to the config.
+    pub(crate) toktx: Option<PathBuf>,
+}
+
+/// Settings for one target platform. Anything that isn't set falls back to
+/// the defaults, and flags given on the command line win over the profile.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Profile {
+    /// The texture format to use for each texture type.
+    pub(crate) formats: HashMap<TextureType, TextureFormat>,
+    /// The ASTC block size to use for each texture type, eg. "6x6".
+    pub(crate) block_sizes: HashMap<TextureType, String>,
+    /// Quality targets for picking block sizes automatically, in the same
+    /// units as `--quality-target`.
+    pub(crate) quality: HashMap<TextureType, f64>,
+    /// The largest width or height a texture can have before it's shrunk.
+    pub(crate) max_size: Option<u32>,
+    pub(crate) supercompression: Option<bool>,
+    pub(crate) auto_block_size: Option<bool>,
+    /// Whether to remove unused resources.
+    pub(crate) prune: Option<bool>,
+    /// Whether to merge duplicate resources.
+    pub(crate) dedup: Option<bool>,
+}
+
+/// Where the cache of compressed textures lives and how big it can get. The
+/// `SQUISHER_CACHE_DIR` and `SQUISHER_CACHE_MAX_SIZE` environment variables
+/// win over these.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct CacheSettings {
+    /// The cache directory, relative to the config.
+    pub(crate) dir: Option<PathBuf>,
+    pub(crate) max_size: Option<ByteSize>,
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs_err::read_to_string(path)?;
+        let mut config: Config =
+            toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))?;
+        if let Some(base) = path.parent() {
+            for path in [&mut config.cache.dir, &mut config.toktx]
+                .into_iter()
+                .flatten()
+            {
+                *path = base.join(&*path);
+            }
+        }
+
+        for (name, profile) in &config.profiles {
+            for (texture_type, block_size) in &profile.block_sizes {
+                if !BLOCK_SIZES.contains(&block_size.as_str()) {
+                    bail!(
+                        "profile '{name}' has an unknown block size '{block_size}' for \
+                        {texture_type:?}, expected one of {}",
+                        BLOCK_SIZES.join(", ")
+                    );
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub(crate) fn profile(&self, name: &str) -> anyhow::Result<&Profile> {
+        self.profiles.get(name).with_context(|| {
+            let mut names: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+            names.sort();
+            format!(
+                "no profile named '{name}', the config has: {}",
+                names.join(", ")
+            )
+        })
+    }
+}
+
+/// Finds the config closest to an input file, looking in its directory and
+/// then each of the directories above it.
+pub fn find(input: &Path) -> Option<PathBuf> {
+    let input = fs_err::canonicalize(input).ok()?;
+    find_in(input.parent()?)
+}
+
+/// Finds the config in a directory, or the closest one in the directories
+/// above it.
+pub fn find_in(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Loads the given config, or the one closest to the input if there is one.
+///
+/// A config that was only found by searching might belong to something
+/// else entirely, so unless its profiles are `needed`, one that can't be
+/// loaded is warned about and the defaults are used instead.
+pub(crate) fn load_config(
+    input: &Path,
+    config: Option<&Path>,
+    needed: bool,
+) -> anyhow::Result<Option<Config>> {
+    if let Some(path) = config {
+        log::debug!("Using config {}", path.display());
+        return Config::load(path).map(Some);
+    }
+    let Some(path) = find(input) else {
+        return Ok(None);
+    };
+
+    log::debug!("Using config {}", path.display());
+    match Config::load(&path) {
+        Ok(config) => Ok(Some(config)),
+        Err(e) if !needed => {
+            log::warn!("Ignoring {}, using the defaults: {e:#}", path.display());
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            [profiles.quest2]
+            max-size = 2048
+            supercompression = false
+            prune = false
+
+            [profiles.quest2.formats]
+            normal = "rgba8"
+
+            [profiles.quest2.block-sizes]
+            base-color = "8x8"
+
+            [profiles.quest2.quality]
+            metallic-roughness-occlusion = 36
+
+            [profiles.pcvr]
+            "#,
+        )
+        .unwrap();
+
+        let quest2 = config.profile("quest2").unwrap();
+        assert_eq!(quest2.max_size, Some(2048));
+        assert_eq!(quest2.supercompression, Some(false));
+        assert_eq!(quest2.prune, Some(false));
+        assert_eq!(quest2.dedup, None);
+        assert_eq!(quest2.formats[&TextureType::Normal], TextureFormat::Rgba8);
+        assert_eq!(quest2.block_sizes[&TextureType::BaseColor], "8x8");
+        assert_eq!(
+            quest2.quality[&TextureType::MetallicRoughnessOcclusion],
+            36.0
+        );
+
+        let error = config.profile("web").unwrap_err().to_string();
+        assert!(error.contains("pcvr, quest2"), "{error}");
+    }
+
+    #[test]
+    fn reject_unknown_settings() {
+        assert!(toml::from_str::<Config>("[profiles.web]\nformat = \"astc\"").is_err());
+        assert!(toml::from_str::<Config>("[profiles.web.formats]\nroughness = \"astc\"").is_err());
+    }
+
+    #[test]
+    fn cache_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        fs_err::write(&path, "[cache]\ndir = \"cache\"\nmax-size = \"4GiB\"\n").unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.cache.dir, Some(dir.path().join("cache")));
+        assert_eq!(config.cache.max_size, Some(ByteSize(4 << 30)));
+        assert_eq!(config.toktx, None);
+    }
+
+    #[test]
+    fn find_closest_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let models = dir.path().join("assets/models");
+        fs_err::create_dir_all(&models).unwrap();
+        fs_err::write(dir.path().join(FILE_NAME), "").unwrap();
+        let input = models.join("box.glb");
+        fs_err::write(&input, "").unwrap();
+
+        let found = find(&input).unwrap();
+        assert_eq!(
+            found,
+            fs_err::canonicalize(dir.path()).unwrap().join(FILE_NAME)
+        );
+
+        fs_err::write(models.join(FILE_NAME), "").unwrap();
+        assert_eq!(
+            find(&input).unwrap().parent().unwrap(),
+            fs_err::canonicalize(&models).unwrap()
+        );
+    }
+}
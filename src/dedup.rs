@@ -0,0 +1,394 @@
+//! Merging of identical images, samplers, textures, materials and accessors.
+//!
+//! Many exported models embed the same PNG several times under different
+//! image indices. Without merging them we'd compress and ship every copy.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use gltf::json::{validation::Checked, Index, Root};
+
+use crate::remap::{IndexMap, Remap};
+
+/// Merges identical images, samplers, textures, materials and accessors, and
+/// removes any buffer views that were only used by the duplicates.
+///
+/// Images and accessors are compared by the hash of their contents, and then
+/// byte for byte when the hashes match, the rest by their properties. Names
+/// are ignored; the merged element keeps the name of the first of its
+/// duplicates.
+pub fn dedup(root: &mut Root, buffers: &[Vec<u8>], base: &Path) -> anyhow::Result<()> {
+    let image_key = |image: &gltf::json::Image| {
+        let bytes = match (&image.buffer_view, &image.uri) {
+            (Some(view), _) => view_data(root, buffers, view.value()).map(Cow::Borrowed),
+            (None, Some(uri)) => crate::read_uri(base, uri).ok().map(Cow::Owned),
+            (None, None) => None,
+        };
+        bytes.map(|bytes| (image.mime_type.as_ref().map(|m| m.0.clone()), bytes))
+    };
+    let images = root
+        .images
+        .iter()
+        .map(|image| image_key(image).map(|key| hash(&key)));
+    let images = canonical_indices_by(images, |first, other| {
+        let first = image_key(&root.images[first]);
+        first.is_some() && first == image_key(&root.images[other])
+    });
+
+    let samplers = root.samplers.iter().map(|sampler| {
+        let mut sampler = sampler.clone();
+        sampler.name = None;
+        json_key(&sampler)
+    });
+    let samplers = canonical_indices(samplers);
+
+    let textures = root.textures.iter().map(|texture| {
+        let mut texture = texture.clone();
+        texture.name = None;
+        texture.source = Index::new(images[texture.source.value()] as _);
+        texture.sampler = texture
+            .sampler
+            .map(|s| Index::new(samplers[s.value()] as _));
+        json_key(&texture)
+    });
+    let textures = canonical_indices(textures);
+
+    let materials = root.materials.iter().map(|material| {
+        let mut material = material.clone();
+        material.name = None;
+
+        let canonical = |index: &mut Index<_>| *index = Index::new(textures[index.value()] as _);
+        let pbr = &mut material.pbr_metallic_roughness;
+        if let Some(info) = &mut pbr.base_color_texture {
+            canonical(&mut info.index);
+        }
+        if let Some(info) = &mut pbr.metallic_roughness_texture {
+            canonical(&mut info.index);
+        }
+        if let Some(info) = &mut material.emissive_texture {
+            canonical(&mut info.index);
+        }
+        if let Some(info) = &mut material.normal_texture {
+            canonical(&mut info.index);
+        }
+        if let Some(info) = &mut material.occlusion_texture {
+            canonical(&mut info.index);
+        }
+
+        json_key(&material)
+    });
+    let materials = canonical_indices(materials);
+
+    let accessors = root
+        .accessors
+        .iter()
+        .map(|accessor| accessor_key(root, buffers, accessor).map(|key| hash(&key)));
+    let accessors = canonical_indices_by(accessors, |first, other| {
+        let first = accessor_key(root, buffers, &root.accessors[first]);
+        first.is_some() && first == accessor_key(root, buffers, &root.accessors[other])
+    });
+
+    // Any buffer views that only the duplicates were using can go too. Views
+    // that weren't used by anything to begin with are left for pruning.
+    let mut used_before = vec![false; root.buffer_views.len()];
+    let mut used_after = vec![false; root.buffer_views.len()];
+    for (index, image) in root.images.iter().enumerate() {
+        if let Some(view) = image.buffer_view {
+            used_before[view.value()] = true;
+            used_after[view.value()] |= images[index] == index;
+        }
+    }
+    for (index, accessor) in root.accessors.iter().enumerate() {
+        let views = accessor.buffer_view.into_iter().chain(
+            accessor
+                .sparse
+                .iter()
+                .flat_map(|s| [s.indices.buffer_view, s.values.buffer_view]),
+        );
+        for view in views {
+            used_before[view.value()] = true;
+            used_after[view.value()] |= accessors[index] == index;
+        }
+    }
+    let buffer_views = used_before
+        .iter()
+        .zip(&used_after)
+        .map(|(before, after)| !before || *after)
+        .collect::<Vec<_>>();
+
+    for (kind, canonical) in [
+        ("images", &images),
+        ("samplers", &samplers),
+        ("textures", &textures),
+        ("materials", &materials),
+        ("accessors", &accessors),
+    ] {
+        let merged = canonical
+            .iter()
+            .enumerate()
+            .filter(|(i, c)| i != *c)
+            .count();
+        if merged > 0 {
+            log::info!("Merging {merged} duplicate {kind}");
+        }
+    }
+
+    let remap = Remap {
+        accessors: IndexMap::merge(&accessors),
+        buffer_views: IndexMap::retain(&buffer_views),
+        images: IndexMap::merge(&images),
+        materials: IndexMap::merge(&materials),
+        meshes: IndexMap::identity(root.meshes.len()),
+        samplers: IndexMap::merge(&samplers),
+        textures: IndexMap::merge(&textures),
+    };
+    remap.apply(root);
+
+    Ok(())
+}
+
+/// For each key, finds the index of the first element with the same key.
+/// Elements without a key are never merged.
+fn canonical_indices<K: Hash + Eq>(keys: impl IntoIterator<Item = Option<K>>) -> Vec<usize> {
+    canonical_indices_by(keys, |_, _| true)
+}
+
+/// Like `canonical_indices`, but elements with the same key are only merged
+/// if `same` agrees, for keys that are hashes of something bigger.
+fn canonical_indices_by<K: Hash + Eq>(
+    keys: impl IntoIterator<Item = Option<K>>,
+    mut same: impl FnMut(usize, usize) -> bool,
+) -> Vec<usize> {
+    let mut seen: HashMap<K, Vec<usize>> = HashMap::new();
+    keys.into_iter()
+        .enumerate()
+        .map(|(index, key)| {
+            let Some(key) = key else {
+                return index;
+            };
+            let candidates = seen.entry(key).or_default();
+            match candidates.iter().find(|first| same(**first, index)) {
+                Some(first) => *first,
+                None => {
+                    candidates.push(index);
+                    index
+                }
+            }
+        })
+        .collect()
+}
+
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = seahash::SeaHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Everything that decides whether two accessors can be merged: their
+/// properties apart from the name and where their data is, what their buffer
+/// view is used for, and the data itself.
+fn accessor_key(
+    root: &Root,
+    buffers: &[Vec<u8>],
+    accessor: &gltf::json::Accessor,
+) -> Option<(String, Option<String>, Vec<u8>)> {
+    // Sparse accessors are rare enough that it's not worth the trouble.
+    if accessor.sparse.is_some() {
+        return None;
+    }
+
+    let data = accessor_data(root, buffers, accessor)?;
+    // Index and vertex data can't share a view, even if the bytes match.
+    let target = match accessor.buffer_view {
+        Some(view) => json_key(&root.buffer_views[view.value()].target)?.into(),
+        None => None,
+    };
+
+    let mut accessor = accessor.clone();
+    accessor.name = None;
+    accessor.buffer_view = None;
+    accessor.byte_offset = 0;
+    Some((json_key(&accessor)?, target, data))
+}
+
+fn json_key<T: serde::Serialize>(value: &T) -> Option<String> {
+    gltf::json::serialize::to_string(value).ok()
+}
+
+fn view_data<'a>(root: &Root, buffers: &'a [Vec<u8>], view: usize) -> Option<&'a [u8]> {
+    let view = root.buffer_views.get(view)?;
+    let start = view.byte_offset.unwrap_or_default() as usize;
+    let end = start + view.byte_length as usize;
+    buffers.get(view.buffer.value())?.get(start..end)
+}
+
+/// Gathers the elements of an accessor into a tightly packed byte vector.
+fn accessor_data(
+    root: &Root,
+    buffers: &[Vec<u8>],
+    accessor: &gltf::json::Accessor,
+) -> Option<Vec<u8>> {
+    let (Checked::Valid(component_type), Checked::Valid(type_)) =
+        (&accessor.component_type, &accessor.type_)
+    else {
+        return None;
+    };
+
+    let element_size = component_type.0.size() * type_.multiplicity();
+    let count = accessor.count as usize;
+    let Some(view) = accessor.buffer_view else {
+        // Accessors without a view are all zeroes.
+        return Some(Vec::new());
+    };
+
+    let stride = root.buffer_views[view.value()]
+        .byte_stride
+        .map_or(element_size, |s| s as usize);
+    let data = view_data(root, buffers, view.value())?;
+    let data = data.get(accessor.byte_offset as usize..)?;
+
+    let mut packed = Vec::with_capacity(element_size * count);
+    for element in 0..count {
+        let start = element * stride;
+        packed.extend_from_slice(data.get(start..start + element_size)?);
+    }
+
+    Some(packed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open;
+
+    #[test]
+    fn merges_duplicate_images_and_materials() {
+        let input = open("test_data/BoxTexturedMultiBuffer.gltf".as_ref()).unwrap();
+        let buffers = input.buffers;
+        let mut root = input.document.into_json();
+
+        // Duplicate the image and everything that uses it.
+        root.buffer_views.push(root.buffer_views[3].clone());
+        let mut image = root.images[0].clone();
+        image.buffer_view = Some(Index::new(4));
+        root.images.push(image);
+
+        let mut texture = root.textures[0].clone();
+        texture.source = Index::new(1);
+        root.textures.push(texture);
+
+        let mut material = root.materials[0].clone();
+        material.name = Some("Copy".into());
+        material
+            .pbr_metallic_roughness
+            .base_color_texture
+            .as_mut()
+            .unwrap()
+            .index = Index::new(1);
+        root.materials.push(material);
+
+        let mut mesh = root.meshes[0].clone();
+        mesh.primitives[0].material = Some(Index::new(1));
+        root.meshes.push(mesh);
+
+        dedup(&mut root, &buffers, "test_data".as_ref()).unwrap();
+
+        assert_eq!(root.images.len(), 1);
+        assert_eq!(root.textures.len(), 1);
+        assert_eq!(root.materials.len(), 1);
+        assert_eq!(root.buffer_views.len(), 4);
+        assert_eq!(root.materials[0].name.as_deref(), Some("Texture"));
+        assert_eq!(root.meshes[1].primitives[0].material.unwrap().value(), 0);
+    }
+
+    #[test]
+    fn merges_duplicate_accessors() {
+        let input = open("test_data/BoxTextured.gltf".as_ref()).unwrap();
+        let buffers = input.buffers;
+        let mut root = input.document.into_json();
+
+        // Point a copy of the positions at a copy of the interleaved view.
+        root.buffer_views.push(root.buffer_views[1].clone());
+        let mut accessor = root.accessors[2].clone();
+        accessor.buffer_view = Some(Index::new(3));
+        root.accessors.push(accessor);
+
+        let mut mesh = root.meshes[0].clone();
+        let primitive = &mut mesh.primitives[0];
+        for accessor in primitive.attributes.values_mut() {
+            if accessor.value() == 2 {
+                *accessor = Index::new(4);
+            }
+        }
+        root.meshes.push(mesh);
+
+        dedup(&mut root, &buffers, "test_data".as_ref()).unwrap();
+
+        assert_eq!(root.accessors.len(), 4);
+        assert_eq!(root.buffer_views.len(), 3);
+        let attributes = |mesh: usize| {
+            let mut attributes = root.meshes[mesh].primitives[0]
+                .attributes
+                .values()
+                .map(Index::value)
+                .collect::<Vec<_>>();
+            attributes.sort();
+            attributes
+        };
+        assert_eq!(attributes(1), attributes(0));
+    }
+
+    #[test]
+    fn keeps_different_accessors() {
+        let input = open("test_data/BoxTextured.gltf".as_ref()).unwrap();
+        let buffers = input.buffers;
+        let mut root = input.document.into_json();
+
+        dedup(&mut root, &buffers, "test_data".as_ref()).unwrap();
+
+        assert_eq!(root.accessors.len(), 4);
+        assert_eq!(root.buffer_views.len(), 3);
+        assert_eq!(root.images.len(), 1);
+    }
+
+    #[test]
+    fn keeps_index_and_vertex_data_apart() {
+        let input = open("test_data/BoxTextured.gltf".as_ref()).unwrap();
+        let buffers = input.buffers;
+        let mut root = input.document.into_json();
+
+        // The indices again, as a vertex attribute in a view of its own.
+        let mut view = root.buffer_views[0].clone();
+        view.target = Some(Checked::Valid(gltf::json::buffer::Target::ArrayBuffer));
+        root.buffer_views.push(view);
+        let mut accessor = root.accessors[0].clone();
+        accessor.buffer_view = Some(Index::new(3));
+        root.accessors.push(accessor);
+
+        let mut mesh = root.meshes[0].clone();
+        let semantic = gltf::json::mesh::Semantic::Extras("ID".into());
+        mesh.primitives[0]
+            .attributes
+            .insert(Checked::Valid(semantic), Index::new(4));
+        root.meshes.push(mesh);
+
+        dedup(&mut root, &buffers, "test_data".as_ref()).unwrap();
+
+        assert_eq!(root.accessors.len(), 5);
+        assert_eq!(root.buffer_views.len(), 4);
+    }
+
+    #[test]
+    fn compares_contents_of_matching_hashes() {
+        // Every element has the same hash, but only some are the same.
+        let contents = ["a", "b", "a", "b"];
+        let canonical = canonical_indices_by([Some(0); 4], |first, other| {
+            contents[first] == contents[other]
+        });
+        assert_eq!(canonical, [0, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,193 @@
+//! The files a squish depends on, for `--watch`, `--depfile` and `--stamp`.
+//!
+//! `--depfile` writes them out for Make or Ninja, so changing an image a
+//! `.gltf` refers to squishes it again. `--stamp` keeps a fingerprint of
+//! them and the settings, so a squish that would make the same output is
+//! skipped:
+//!
+//! ```text
+//! output.glb: model.gltf model.bin textures/wood.png squisher.toml
+//! ```
+
+use std::{
+    hash::Hasher,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use filetime::FileTime;
+
+use crate::{config, overrides, toktx::Toktx, uri_path, Args, Input};
+
+/// The input, any buffers and images it refers to by URI, its texture
+/// overrides and its config. Some of them might not exist, eg. when there
+/// aren't any overrides.
+pub(crate) fn dependencies(args: &Args, input: &Input) -> Vec<PathBuf> {
+    let mut files = model_files(&args.input, input);
+    files.push(overrides::Sidecar::path(&args.input));
+    files.extend(args.config.clone().or_else(|| config::find(&args.input)));
+    files
+}
+
+/// The input and every buffer and image it refers to by URI, once each.
+pub(crate) fn model_files(path: &Path, input: &Input) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+
+    let json = input.document.clone().into_json();
+    let uris = json
+        .buffers
+        .iter()
+        .filter_map(|buffer| buffer.uri.as_deref())
+        .chain(json.images.iter().filter_map(|image| image.uri.as_deref()))
+        .filter(|uri| !uri.starts_with("data:"));
+    for file in uris.filter_map(|uri| uri_path(&input.base, uri).ok()) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+
+    files
+}
+
+/// Writes a Make/Ninja depfile saying the outputs depend on every
+/// dependency that exists. Make fails on missing ones, so a sidecar or config
+/// added later won't be noticed until something else changes.
+pub(crate) fn write_depfile(
+    path: &Path,
+    outputs: &[PathBuf],
+    dependencies: &[PathBuf],
+) -> anyhow::Result<()> {
+    let mut depfile = outputs
+        .iter()
+        .map(|p| escape(p))
+        .collect::<Vec<_>>()
+        .join(" ");
+    depfile.push(':');
+    for dependency in dependencies.iter().filter(|path| path.exists()) {
+        depfile.push_str(" \\\n  ");
+        depfile.push_str(&escape(dependency));
+    }
+    depfile.push('\n');
+
+    fs_err::write(path, depfile).context("failed to write depfile")
+}
+
+/// Escapes a path the way both Make and Ninja read it.
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A hash of everything that decides what the outputs look like: the
+/// settings, the versions of squisher and `toktx`, and the contents of the
+/// dependencies.
+pub(crate) fn fingerprint(
+    args: &Args,
+    toktx: &Toktx,
+    dependencies: &[PathBuf],
+) -> anyhow::Result<String> {
+    let mut hasher = seahash::SeaHasher::new();
+
+    // The profiles themselves are covered by hashing the config file.
+    let settings = format!(
+        "{:?}",
+        (
+            (&args.input, &args.output, &args.profile, &args.config),
+            (args.format, args.max_size, &args.quality_target),
+            (
+                args.no_supercompression,
+                args.no_prune,
+                args.no_dedup,
+                args.auto_block_size,
+                args.allow_invalid,
+                args.keep_going,
+            ),
+            (
+                args.max_file_size,
+                args.max_gpu_memory,
+                args.max_texture_size
+            ),
+            (&args.report, &args.depfile),
+            (env!("CARGO_PKG_VERSION"), toktx.version()),
+        )
+    );
+    hasher.write(settings.as_bytes());
+
+    for dependency in dependencies {
+        hasher.write(dependency.to_string_lossy().as_bytes());
+        match fs_err::read(dependency) {
+            Ok(contents) => {
+                hasher.write_u8(1);
+                hasher.write(&contents);
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => hasher.write_u8(0),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Whether the stamp was written with this fingerprint and all the outputs,
+/// including any size reports, are still there. If they are, the outputs are
+/// touched so build systems see them as newer than the inputs.
+pub(crate) fn is_up_to_date(
+    stamp: &Path,
+    fingerprint: &str,
+    outputs: &[PathBuf],
+) -> anyhow::Result<bool> {
+    let previous = match fs_err::read_to_string(stamp) {
+        Ok(previous) => previous,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    if previous.trim() != fingerprint || !outputs.iter().all(|output| output.is_file()) {
+        return Ok(false);
+    }
+
+    for output in outputs {
+        filetime::set_file_mtime(output, FileTime::now())
+            .with_context(|| format!("failed to touch {}", output.display()))?;
+    }
+    Ok(true)
+}
+
+pub(crate) fn write_stamp(stamp: &Path, fingerprint: &str) -> anyhow::Result<()> {
+    fs_err::write(stamp, format!("{fingerprint}\n")).context("failed to write stamp")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let texture = dir.path().join("my textures#1.png");
+        fs_err::write(&texture, "png").unwrap();
+        let depfile = dir.path().join("out.d");
+
+        write_depfile(
+            &depfile,
+            &["out$.glb".into()],
+            &[texture.clone(), dir.path().join("missing.bin")],
+        )
+        .unwrap();
+
+        let depfile = fs_err::read_to_string(&depfile).unwrap();
+        let texture = texture.to_string_lossy().replace(' ', "\\ ");
+        assert_eq!(
+            depfile,
+            format!("out$$.glb: \\\n  {}\n", texture.replace('#', "\\#"))
+        );
+    }
+}
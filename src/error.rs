@@ -0,0 +1,191 @@
+//! The kinds of failure squisher can report, so scripts and tools can tell
+//! "toktx is missing" apart from "the input isn't a GLB" without matching on
+//! messages.
+//!
+//! They're attached to `anyhow` errors as context, so the underlying cause is
+//! still there when the error is printed, and found again with
+//! [`SquishError::find`].
+
+use std::{fmt, path::PathBuf};
+
+use crate::{validate::Issue, TextureType};
+
+/// Something that went wrong while squishing. Each kind has its own exit
+/// status, which won't change between versions.
+#[derive(Debug)]
+pub enum SquishError {
+    /// The input couldn't be read, or isn't a glTF or GLB file. Exits with 3.
+    InvalidInput { path: PathBuf },
+    /// The input uses something squisher can't handle, like an image MIME
+    /// type or URI scheme. Exits with 4.
+    Unsupported {
+        feature: String,
+        image: Option<Object>,
+    },
+    /// `toktx` couldn't be found, or is too old. Exits with 5.
+    ToktxUnavailable,
+    /// A texture couldn't be decoded or compressed. Exits with 6.
+    Encode {
+        image: Object,
+        /// The material the texture was used by, unless it's the default.
+        material: Option<Object>,
+        texture_type: TextureType,
+    },
+    /// The cache couldn't be read or written. Exits with 7.
+    Cache { dir: PathBuf },
+    /// The output failed validation. Exits with 8.
+    Validation { issues: Vec<Issue> },
+    /// The output went over a budget. Exits with 9.
+    OverBudget { problems: Vec<String> },
+    /// The output was written, but `--keep-going` left this many images
+    /// uncompressed. Exits with 2.
+    Partial { count: usize },
+    /// The squish was cancelled before it finished, eg. by a `cancel`
+    /// request to `squisher serve`. Exits with 10.
+    Cancelled,
+}
+
+/// An object in a glTF document, eg. an image or material.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub index: usize,
+    pub name: Option<String>,
+}
+
+impl SquishError {
+    /// Finds the kind of failure an error is, if it's one of ours.
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref()
+    }
+
+    /// Adds this kind to an error, unless it already has one, like an
+    /// unsupported URI scheme found while loading a buffer.
+    pub fn or_existing(self, error: anyhow::Error) -> anyhow::Error {
+        match Self::find(&error) {
+            Some(_) => error,
+            None => error.context(self),
+        }
+    }
+
+    /// The status the process exits with. Anything that isn't a
+    /// `SquishError` exits with 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SquishError::Partial { .. } => 2,
+            SquishError::InvalidInput { .. } => 3,
+            SquishError::Unsupported { .. } => 4,
+            SquishError::ToktxUnavailable => 5,
+            SquishError::Encode { .. } => 6,
+            SquishError::Cache { .. } => 7,
+            SquishError::Validation { .. } => 8,
+            SquishError::OverBudget { .. } => 9,
+            SquishError::Cancelled => 10,
+        }
+    }
+}
+
+impl fmt::Display for SquishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SquishError::InvalidInput { path } => write!(f, "unable to read {}", path.display()),
+            SquishError::Unsupported { feature, image } => {
+                write!(f, "{feature} isn't supported")?;
+                if let Some(image) = image {
+                    write!(f, " (image {image})")?;
+                }
+                Ok(())
+            }
+            SquishError::ToktxUnavailable => write!(f, "unable to find a usable toktx"),
+            SquishError::Encode {
+                image,
+                material,
+                texture_type,
+            } => {
+                write!(f, "failed to compress image {image} as {texture_type:?}")?;
+                if let Some(material) = material {
+                    write!(f, " for material {material}")?;
+                }
+                Ok(())
+            }
+            SquishError::Cache { dir } => write!(f, "unable to use the cache in {}", dir.display()),
+            SquishError::Validation { issues } => {
+                write!(
+                    f,
+                    "output failed validation (use --allow-invalid to write it anyway):"
+                )?;
+                issues.iter().try_for_each(|issue| write!(f, "\n  {issue}"))
+            }
+            SquishError::OverBudget { problems } => {
+                write!(f, "output is over budget:")?;
+                problems
+                    .iter()
+                    .try_for_each(|problem| write!(f, "\n  {problem}"))
+            }
+            SquishError::Partial { count } => write!(
+                f,
+                "Squished with {count} image(s) left uncompressed, see the report for why"
+            ),
+            SquishError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for SquishError {}
+
+impl Object {
+    pub fn image(image: &gltf::Image) -> Self {
+        Object {
+            index: image.index(),
+            name: image.name().map(str::to_string),
+        }
+    }
+
+    /// The material, unless it's the default material, which has no index.
+    pub fn material(material: &gltf::Material) -> Option<Self> {
+        Some(Object {
+            index: material.index()?,
+            name: material.name().map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "#{} \"{name}\"", self.index),
+            None => write!(f, "#{}", self.index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn find_through_context() {
+        let error = std::fs::read("does/not/exist.png")
+            .context(SquishError::Encode {
+                image: Object {
+                    index: 3,
+                    name: Some("Wood".into()),
+                },
+                material: None,
+                texture_type: TextureType::BaseColor,
+            })
+            .context("while squishing for quest2")
+            .unwrap_err();
+
+        let kind = SquishError::find(&error).unwrap();
+        assert_eq!(kind.exit_code(), 6);
+        assert!(matches!(kind, SquishError::Encode { image, .. } if image.index == 3));
+        assert_eq!(
+            format!("{error:#}").split(": ").nth(1),
+            Some("failed to compress image #3 \"Wood\" as BaseColor")
+        );
+
+        let error = anyhow::Error::new(std::fmt::Error);
+        assert!(SquishError::find(&error).is_none());
+    }
+}
@@ -0,0 +1,208 @@
+//! The `extract` subcommand, which writes the images in a file out on their
+//! own so they can be looked at.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::{open, unsquish, Input};
+
+#[derive(clap::Args)]
+pub struct ExtractArgs {
+    /// The path to the file to extract images from.
+    input: PathBuf,
+
+    /// The directory to write the images to. It's created if it doesn't
+    /// exist.
+    output: PathBuf,
+
+    /// Also decode KTX2 images to PNG, writing them next to the originals.
+    #[clap(long)]
+    decode: bool,
+
+    /// Enables more verbose logging.
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+pub fn extract(args: ExtractArgs) -> anyhow::Result<()> {
+    crate::configure_logging(args.verbose);
+
+    let input = open(&args.input)?;
+    fs_err::create_dir_all(&args.output)?;
+
+    let normal_maps = unsquish::normal_map_images(&input.document);
+    let mut used_names = HashSet::new();
+    for image in input.document.images() {
+        let index = image.index();
+        let (bytes, mime_type) = input.image_data(&image)?;
+        let extension = match mime_type {
+            Some("image/png") => "png",
+            Some("image/jpeg") => "jpg",
+            Some("image/ktx2") => "ktx2",
+            _ => "bin",
+        };
+
+        let name = unique_name(&mut used_names, image_name(&input, &image), index);
+
+        let path = args.output.join(format!("{name}.{extension}"));
+        fs_err::write(&path, &bytes)?;
+        log::info!("Wrote image {index} to {}", path.display());
+
+        if args.decode && extension == "ktx2" {
+            let png = unsquish::ktx2_to_png(&bytes, normal_maps[index])
+                .with_context(|| format!("failed to decode image {index}"))?;
+            let path = args.output.join(format!("{name}.png"));
+            fs_err::write(&path, png)?;
+            log::info!("Wrote decoded image {index} to {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Names an image after itself, the first texture that uses it or the first
+/// material slot it's used in, in that order, falling back to its index.
+fn image_name(input: &Input, image: &gltf::Image) -> String {
+    let document = &input.document;
+    let index = image.index();
+
+    let texture_name = || {
+        document
+            .textures()
+            .filter(|texture| texture.source().index() == index)
+            .find_map(|texture| texture.name().map(String::from))
+    };
+
+    let material_name = || {
+        document.materials().find_map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            let slots = [
+                ("baseColor", pbr.base_color_texture().map(|t| t.texture())),
+                (
+                    "metallicRoughness",
+                    pbr.metallic_roughness_texture().map(|t| t.texture()),
+                ),
+                ("normal", material.normal_texture().map(|t| t.texture())),
+                (
+                    "occlusion",
+                    material.occlusion_texture().map(|t| t.texture()),
+                ),
+                ("emissive", material.emissive_texture().map(|t| t.texture())),
+            ];
+
+            let name = material.name()?;
+            slots.into_iter().find_map(|(slot, texture)| {
+                (texture?.source().index() == index).then(|| format!("{name}_{slot}"))
+            })
+        })
+    };
+
+    let name = image
+        .name()
+        .map(String::from)
+        .or_else(texture_name)
+        .or_else(material_name)
+        .map(|name| sanitize(&name))
+        .filter(|name| !name.is_empty());
+
+    name.unwrap_or_else(|| format!("image_{index}"))
+}
+
+/// Adds the image's index to its name if another image already has that
+/// name, and then a counter if even that's taken, eg. by an image that's
+/// really called `Wood_3`.
+fn unique_name(used_names: &mut HashSet<String>, name: String, index: usize) -> String {
+    let mut unique = name.clone();
+    let mut counter = 1;
+    while used_names.contains(&unique) {
+        unique = match counter {
+            1 => format!("{name}_{index}"),
+            _ => format!("{name}_{index}_{counter}"),
+        };
+        counter += 1;
+    }
+    used_names.insert(unique.clone());
+    unique
+}
+
+/// Replaces anything that isn't safe to use in a file name.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' | ' ' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_matches(|c| c == '.' || c == ' ')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_box_textured() {
+        let output = PathBuf::from("test_output/extract_box_textured");
+        let _ = fs_err::remove_dir_all(&output);
+
+        extract(ExtractArgs {
+            input: "test_data/BoxTexturedBinary.glb".into(),
+            output: output.clone(),
+            decode: true,
+            verbose: false,
+        })
+        .unwrap();
+
+        let png = fs_err::read(output.join("Texture_baseColor.png")).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 256);
+    }
+
+    #[test]
+    fn extract_decoded_ktx2() {
+        let output = PathBuf::from("test_output/extract_decoded_ktx2");
+        let _ = fs_err::remove_dir_all(&output);
+        fs_err::create_dir_all(&output).unwrap();
+
+        let pixels = vec![255, 0, 0, 255, 0, 255, 0, 255];
+        let image = image::RgbaImage::from_raw(2, 1, pixels).unwrap();
+        let ktx2 = crate::quality::tests::rgba8_ktx2(&image);
+        let input = open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let images = [(0, (ktx2.as_slice(), "image/ktx2"))].into_iter().collect();
+        let (root, blob) = crate::pack_buffers(input, &images).unwrap();
+        let glb = output.join("box.glb");
+        fs_err::write(&glb, crate::to_glb(&root, blob).unwrap()).unwrap();
+
+        extract(ExtractArgs {
+            input: glb,
+            output: output.clone(),
+            decode: true,
+            verbose: false,
+        })
+        .unwrap();
+
+        assert_eq!(
+            fs_err::read(output.join("Texture_baseColor.ktx2")).unwrap(),
+            ktx2
+        );
+        let png = fs_err::read(output.join("Texture_baseColor.png")).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image);
+    }
+
+    #[test]
+    fn unique_names() {
+        let mut used_names = HashSet::new();
+        assert_eq!(unique_name(&mut used_names, "Wood".into(), 0), "Wood");
+        assert_eq!(unique_name(&mut used_names, "Wood_3".into(), 1), "Wood_3");
+        assert_eq!(unique_name(&mut used_names, "Wood".into(), 2), "Wood_2");
+        assert_eq!(unique_name(&mut used_names, "Wood".into(), 3), "Wood_3_2");
+        assert_eq!(unique_name(&mut used_names, "Wood_3".into(), 4), "Wood_3_4");
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize("Wood/Floor: Diffuse"), "Wood_Floor_ Diffuse");
+        assert_eq!(sanitize("../secret"), "_secret");
+    }
+}
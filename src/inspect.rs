@@ -0,0 +1,374 @@
+//! The `inspect` subcommand, which reports what's inside a glTF or GLB file.
+
+use std::{collections::BTreeSet, fmt, io, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{budget::estimate_gpu_bytes, open, quality::read_ktx2, read_uri, uri_mime_type, Input};
+
+#[derive(clap::Args)]
+pub struct InspectArgs {
+    /// The path to the file to inspect.
+    input: PathBuf,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+/// Everything we know about the contents of a file.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub images: Vec<ImageReport>,
+    pub meshes: Vec<MeshReport>,
+    pub totals: Totals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageReport {
+    pub index: usize,
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Only present for KTX2 images.
+    pub ktx2: Option<Ktx2Report>,
+    pub bytes: usize,
+    /// An estimate of how much GPU memory the texture will take up.
+    pub gpu_bytes: usize,
+    pub used_by: Vec<ImageUse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ktx2Report {
+    pub format: Option<String>,
+    pub levels: u32,
+    pub supercompression: Option<String>,
+}
+
+/// A material slot that an image is used in.
+#[derive(Debug, Serialize)]
+pub struct ImageUse {
+    pub material: usize,
+    pub material_name: Option<String>,
+    pub slot: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MeshReport {
+    pub index: usize,
+    pub name: Option<String>,
+    pub primitives: usize,
+    pub vertices: usize,
+    pub indices: usize,
+}
+
+/// The number of bytes used by each part of the file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub images: usize,
+    pub geometry: usize,
+    pub json: usize,
+}
+
+impl Totals {
+    pub fn total(&self) -> usize {
+        self.images + self.geometry + self.json
+    }
+}
+
+pub fn inspect(args: InspectArgs) -> anyhow::Result<()> {
+    crate::configure_logging(false);
+
+    let input = open(&args.input)?;
+    let report = Report::new(&input)?;
+
+    if args.json {
+        println!("{}", gltf::json::serialize::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+
+    Ok(())
+}
+
+impl Report {
+    pub(crate) fn new(input: &Input) -> anyhow::Result<Self> {
+        let document = &input.document;
+        let mut totals = Totals::default();
+
+        let mut images: Vec<_> = document
+            .images()
+            .map(|image| {
+                let (bytes, mime_type) = match image.source() {
+                    gltf::image::Source::View { view, mime_type } => {
+                        (Some(input.view_data(&view).to_vec()), Some(mime_type))
+                    }
+                    gltf::image::Source::Uri { uri, mime_type } => {
+                        let bytes = read_uri(&input.base, uri)
+                            .map_err(|e| log::warn!("Unable to read image {uri}: {e:#}"))
+                            .ok();
+                        (bytes, mime_type.or_else(|| uri_mime_type(uri)))
+                    }
+                };
+
+                let bytes = bytes.unwrap_or_default();
+                totals.images += bytes.len();
+                describe_image(image.index(), image.name(), mime_type, &bytes)
+            })
+            .collect();
+
+        for material in document.materials() {
+            let pbr = material.pbr_metallic_roughness();
+            let slots = [
+                (
+                    "baseColorTexture",
+                    pbr.base_color_texture().map(|t| t.texture()),
+                ),
+                (
+                    "metallicRoughnessTexture",
+                    pbr.metallic_roughness_texture().map(|t| t.texture()),
+                ),
+                (
+                    "normalTexture",
+                    material.normal_texture().map(|t| t.texture()),
+                ),
+                (
+                    "occlusionTexture",
+                    material.occlusion_texture().map(|t| t.texture()),
+                ),
+                (
+                    "emissiveTexture",
+                    material.emissive_texture().map(|t| t.texture()),
+                ),
+            ];
+
+            for (slot, texture) in slots {
+                let Some(texture) = texture else { continue };
+                // The default material has no index, and no textures either.
+                let Some(material_index) = material.index() else {
+                    continue;
+                };
+
+                images[texture.source().index()].used_by.push(ImageUse {
+                    material: material_index,
+                    material_name: material.name().map(Into::into),
+                    slot,
+                });
+            }
+        }
+
+        let meshes = document
+            .meshes()
+            .map(|mesh| {
+                let primitives = mesh.primitives();
+                let mut report = MeshReport {
+                    index: mesh.index(),
+                    name: mesh.name().map(Into::into),
+                    primitives: primitives.len(),
+                    vertices: 0,
+                    indices: 0,
+                };
+
+                for primitive in primitives {
+                    if let Some(positions) = primitive.get(&gltf::Semantic::Positions) {
+                        report.vertices += positions.count();
+                    }
+                    if let Some(indices) = primitive.indices() {
+                        report.indices += indices.count();
+                    }
+                }
+
+                report
+            })
+            .collect();
+
+        // Count each buffer view that holds geometry once, no matter how many
+        // accessors use it.
+        let mut geometry_views = BTreeSet::new();
+        for accessor in document.accessors() {
+            geometry_views.extend(accessor.view().map(|v| (v.index(), v.length())));
+            if let Some(sparse) = accessor.sparse() {
+                let indices = sparse.indices().view();
+                let values = sparse.values().view();
+                geometry_views.insert((indices.index(), indices.length()));
+                geometry_views.insert((values.index(), values.length()));
+            }
+        }
+        totals.geometry = geometry_views.iter().map(|(_, length)| length).sum();
+        totals.json = gltf::json::serialize::to_string(&document.clone().into_json())?.len();
+
+        Ok(Report {
+            images,
+            meshes,
+            totals,
+        })
+    }
+}
+
+fn describe_image(
+    index: usize,
+    name: Option<&str>,
+    mime_type: Option<&str>,
+    bytes: &[u8],
+) -> ImageReport {
+    let mut report = ImageReport {
+        index,
+        name: name.map(Into::into),
+        mime_type: mime_type.map(Into::into),
+        width: None,
+        height: None,
+        ktx2: None,
+        bytes: bytes.len(),
+        gpu_bytes: 0,
+        used_by: Vec::new(),
+    };
+
+    if mime_type == Some("image/ktx2") {
+        match read_ktx2(bytes) {
+            Ok(reader) => {
+                let header = reader.header();
+                report.width = Some(header.pixel_width);
+                report.height = Some(header.pixel_height);
+                report.ktx2 = Some(Ktx2Report {
+                    format: header.format.map(|f| format!("{f:?}")),
+                    levels: header.level_count,
+                    supercompression: header.supercompression_scheme.map(|s| format!("{s:?}")),
+                });
+                report.gpu_bytes = estimate_gpu_bytes(
+                    header.format,
+                    header.pixel_width,
+                    header.pixel_height,
+                    header.level_count,
+                );
+            }
+            Err(e) => log::warn!("Image {index} is not a valid KTX2 file: {e:#}"),
+        }
+    } else if !bytes.is_empty() {
+        let dimensions = image::io::Reader::new(io::Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.into_dimensions());
+        match dimensions {
+            Ok((width, height)) => {
+                report.width = Some(width);
+                report.height = Some(height);
+                report.gpu_bytes = estimate_gpu_bytes(None, width, height, 0);
+            }
+            Err(e) => log::warn!("Unable to read the size of image {index}: {e}"),
+        }
+    }
+
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Images:")?;
+        for image in &self.images {
+            write!(f, "  #{}", image.index)?;
+            if let Some(name) = &image.name {
+                write!(f, " \"{name}\"")?;
+            }
+            write!(f, " {}", image.mime_type.as_deref().unwrap_or("unknown"))?;
+            if let (Some(width), Some(height)) = (image.width, image.height) {
+                write!(f, " {width}x{height}")?;
+            }
+            if let Some(ktx2) = &image.ktx2 {
+                write!(
+                    f,
+                    " {}, {} levels",
+                    ktx2.format.as_deref().unwrap_or("UNDEFINED"),
+                    ktx2.levels
+                )?;
+                if let Some(supercompression) = &ktx2.supercompression {
+                    write!(f, ", {supercompression}")?;
+                }
+            }
+            writeln!(
+                f,
+                ", {} (~{} GPU)",
+                format_bytes(image.bytes),
+                format_bytes(image.gpu_bytes)
+            )?;
+
+            for image_use in &image.used_by {
+                write!(f, "      used by material #{}", image_use.material)?;
+                if let Some(name) = &image_use.material_name {
+                    write!(f, " \"{name}\"")?;
+                }
+                writeln!(f, " as {}", image_use.slot)?;
+            }
+        }
+
+        writeln!(f, "Meshes:")?;
+        for mesh in &self.meshes {
+            write!(f, "  #{}", mesh.index)?;
+            if let Some(name) = &mesh.name {
+                write!(f, " \"{name}\"")?;
+            }
+            writeln!(
+                f,
+                ": {} primitives, {} vertices, {} indices",
+                mesh.primitives, mesh.vertices, mesh.indices
+            )?;
+        }
+
+        writeln!(f, "Totals:")?;
+        writeln!(f, "  images   {:>10}", format_bytes(self.totals.images))?;
+        writeln!(f, "  geometry {:>10}", format_bytes(self.totals.geometry))?;
+        writeln!(f, "  JSON     {:>10}", format_bytes(self.totals.json))?;
+        writeln!(f, "  total    {:>10}", format_bytes(self.totals.total()))
+    }
+}
+
+/// Formats a number of bytes for humans, like `12.3 KiB`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_textured_binary() {
+        let input = open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let report = Report::new(&input).unwrap();
+
+        assert_eq!(report.images.len(), 1);
+        let image = &report.images[0];
+        assert_eq!(image.mime_type.as_deref(), Some("image/png"));
+        assert_eq!((image.width, image.height), (Some(256), Some(256)));
+        assert!(image.ktx2.is_none());
+        assert_eq!(image.gpu_bytes, 349_524);
+        assert_eq!(image.used_by.len(), 1);
+        assert_eq!(image.used_by[0].slot, "baseColorTexture");
+
+        assert_eq!(report.meshes.len(), 1);
+        assert_eq!(report.meshes[0].vertices, 24);
+        assert_eq!(report.meshes[0].indices, 36);
+        assert_eq!(report.totals.geometry, 840);
+        assert_eq!(report.totals.images, image.bytes);
+    }
+
+    #[test]
+    fn bytes_for_humans() {
+        assert_eq!(format_bytes(12), "12 B");
+        assert_eq!(format_bytes(2048), "2.0 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 + 512 * 1024), "3.5 MiB");
+    }
+}
@@ -0,0 +1,2170 @@
+//! Creates optimised, platform specific glTF files.
+//!
+//! This is the `squisher` command line tool, which [`run`] starts. Tools that
+//! run squishes themselves can tell failures apart with [`SquishError`].
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::Hasher,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use gltf::json::{image::MimeType, Index};
+
+mod budget;
+mod cache;
+mod config;
+mod dedup;
+mod deps;
+mod error;
+mod extract;
+mod inspect;
+mod overrides;
+mod progress;
+mod prune;
+mod quality;
+mod remap;
+mod report;
+mod reproducible;
+mod serve;
+mod sources;
+mod toktx;
+mod unsquish;
+mod validate;
+mod watch;
+
+pub use error::{Object, SquishError};
+pub use validate::Issue;
+
+/// The largest width or height a texture can have before it's shrunk, unless
+/// a profile or `--max-size` says otherwise.
+const MAX_SIZE: u32 = 4096;
+
+#[derive(Parser)]
+#[command(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    squish: Option<Args>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Lists the images, materials and meshes in a glTF or GLB file.
+    Inspect(inspect::InspectArgs),
+    /// Converts a squished file back into one with PNG images, so it can be
+    /// opened in any glTF viewer.
+    Unsquish(unsquish::UnsquishArgs),
+    /// Writes every image in a glTF or GLB file out to a directory.
+    Extract(extract::ExtractArgs),
+    /// Shows, clears or prunes the cache of compressed textures.
+    Cache(cache::CacheArgs),
+    /// Keeps running and squishes files on request, over JSON-RPC on stdin
+    /// and stdout or a Unix socket.
+    Serve(serve::ServeArgs),
+}
+
+#[derive(clap::Args)]
+struct Args {
+    /// The path to the file to process.
+    input: PathBuf,
+
+    /// Where to output the squished output.
+    output: PathBuf,
+
+    /// What texture format to use. Can be 'astc' (default) or 'rgba8'.
+    #[clap(long)]
+    format: Option<TextureFormat>,
+
+    /// Use the settings from a profile in the closest squisher.toml, looking
+    /// in the input's directory and then each directory above it. Flags given
+    /// on the command line take precedence over the profile.
+    ///
+    /// Giving several profiles, eg. 'quest2,pcvr', writes one output for
+    /// each, named after the profile ('model.quest2.glb').
+    #[clap(long, value_delimiter = ',')]
+    profile: Vec<String>,
+
+    /// Read profiles and settings from this file instead of searching for
+    /// squisher.toml.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Shrink textures that are wider or taller than this many pixels. The
+    /// default is 4096.
+    #[clap(long)]
+    max_size: Option<u32>,
+
+    /// Enables more verbose logging.
+    #[clap(short, long)]
+    verbose: bool,
+
+    /// Disable the image cache, forcing all images to be reprocessed.
+    #[clap(long)]
+    no_cache: bool,
+
+    /// Disable using Zstandard supercompression on the images.
+    #[clap(long)]
+    no_supercompression: bool,
+
+    /// Keep resources that aren't used by any scene in the output.
+    #[clap(long)]
+    no_prune: bool,
+
+    /// Keep duplicate images, textures, materials and accessors in the output.
+    #[clap(long)]
+    no_dedup: bool,
+
+    /// Write the output even if it fails validation, logging the problems as
+    /// warnings instead.
+    #[clap(long)]
+    allow_invalid: bool,
+
+    /// Leave textures that fail to decode or compress in their original
+    /// format instead of giving up, and exit with status 2 once the output is
+    /// written.
+    #[clap(long)]
+    keep_going: bool,
+
+    /// How many seconds toktx gets to compress each texture before it's
+    /// killed.
+    #[clap(long, default_value_t = 600)]
+    toktx_timeout: u64,
+
+    /// Keep running, squishing the input again every time it, the files it
+    /// refers to or its config change.
+    #[clap(long)]
+    watch: bool,
+
+    /// Report progress as one JSON event per line on stdout, for tools that
+    /// wrap squisher. The only format is 'json'.
+    #[clap(long, value_name = "FORMAT")]
+    progress: Option<progress::ProgressFormat>,
+
+    /// Write a Make/Ninja depfile to this path, listing the input and every
+    /// file it pulled in.
+    #[clap(long, value_name = "PATH")]
+    depfile: Option<PathBuf>,
+
+    /// Skip squishing if the inputs and settings haven't changed since this
+    /// stamp file was last written, and write it after squishing.
+    #[clap(long, value_name = "PATH")]
+    stamp: Option<PathBuf>,
+
+    /// Also write the size report as JSON to this path.
+    #[clap(long)]
+    report: Option<PathBuf>,
+
+    /// Fail if the output file is larger than this, eg. '20MiB'.
+    #[clap(long)]
+    max_file_size: Option<budget::ByteSize>,
+
+    /// Fail if the textures in the output would take up more GPU memory than
+    /// this, including their mip chains, eg. '256MiB'.
+    #[clap(long)]
+    max_gpu_memory: Option<budget::ByteSize>,
+
+    /// Fail if any texture in the output is wider or taller than this many
+    /// pixels.
+    #[clap(long)]
+    max_texture_size: Option<u32>,
+
+    /// Try several ASTC block sizes for each texture and use the largest one
+    /// that still meets the quality target for its texture type.
+    #[clap(long)]
+    auto_block_size: bool,
+
+    /// Overrides the quality target used by --auto-block-size for a texture
+    /// type, eg. 'base-color=40' for a minimum PSNR of 40dB, or 'normal=3'
+    /// for a maximum mean error of 3 degrees. Can be given more than once.
+    #[clap(long, value_name = "TYPE=VALUE")]
+    quality_target: Vec<quality::QualityTarget>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextureFormat {
+    Rgba8,
+    Astc,
+}
+
+impl FromStr for TextureFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgba8" => Ok(Self::Rgba8),
+            "astc" => Ok(Self::Astc),
+            _ => bail!("unknown texture format '{s}', expected 'rgba8' or 'astc'"),
+        }
+    }
+}
+
+impl TextureFormat {
+    /// Whether `toktx` stores normal maps in this format with X in the colour
+    /// channels and Y in alpha. `--normal_mode` only changes block compressed
+    /// formats, so uncompressed ones are left as ordinary normal maps.
+    fn packs_normal_maps(self) -> bool {
+        self == TextureFormat::Astc
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TextureFormat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Runs the command line tool with the process's arguments, exiting with the
+/// status for the kind of failure if there is one.
+pub fn run() {
+    let cli = Cli::parse();
+
+    let result = match (cli.command, cli.squish) {
+        (Some(Commands::Inspect(args)), _) => inspect::inspect(args),
+        (Some(Commands::Unsquish(args)), _) => unsquish::unsquish(args),
+        (Some(Commands::Extract(args)), _) => extract::extract(args),
+        (Some(Commands::Cache(args)), _) => cache::cache(args),
+        (Some(Commands::Serve(args)), _) => serve::serve(args),
+        (None, Some(args)) => squish(args),
+        // clap makes sure we get one or the other.
+        (None, None) => unreachable!(),
+    };
+
+    if let Err(err) = result {
+        let kind = SquishError::find(&err);
+        if let Some(partial @ SquishError::Partial { .. }) = kind {
+            log::warn!("{partial}");
+        } else {
+            log::error!("Fatal error: {err:?}");
+        }
+        std::process::exit(kind.map_or(1, SquishError::exit_code));
+    }
+}
+
+struct SquishContext {
+    input: Input,
+    /// Where to find and keep compressed textures, unless it's disabled.
+    cache: Option<Rc<cache::Cache>>,
+    /// The `toktx` to compress textures with.
+    toktx: Rc<toktx::Toktx>,
+    use_supercompression: bool,
+    allow_invalid: bool,
+    /// Leave textures that fail to compress as they are, rather than failing.
+    keep_going: bool,
+    max_size: u32,
+    /// How to compress each type of texture.
+    textures: HashMap<TextureType, TextureSettings>,
+    /// Decoded and resized images, shared with every other target.
+    sources: Rc<sources::Sources>,
+    /// Set when the squish should stop before the next texture.
+    cancelled: Arc<AtomicBool>,
+    /// Settings for individual textures from `model.squisher.toml`.
+    sidecar: Rc<overrides::Sidecar>,
+    progress: progress::Progress,
+}
+
+/// How to compress one type of texture.
+#[derive(Debug, Clone, Copy)]
+struct TextureSettings {
+    format: TextureFormat,
+    /// The ASTC block size, if we're not picking one automatically.
+    block_size: &'static str,
+    /// The quality the texture has to meet when picking its block size
+    /// automatically, or `None` to always use `block_size`.
+    quality_target: Option<quality::QualityTarget>,
+}
+
+#[derive(Clone)]
+struct Input {
+    document: gltf::Document,
+    /// The contents of each buffer in the document, in the same order.
+    buffers: Vec<Vec<u8>>,
+    /// The directory that relative URIs in the document are resolved against.
+    base: PathBuf,
+}
+
+/// A texture that has been through `toktx`.
+struct CompressedTexture {
+    bytes: Vec<u8>,
+    texture_type: TextureType,
+    /// Whether the bytes came from the cache rather than from `toktx`.
+    cached: bool,
+    /// How close the texture is to the source image, if we could tell.
+    quality: Option<quality::Metrics>,
+}
+
+/// The result of squishing a file.
+struct Squished {
+    glb: Vec<u8>,
+    /// How each image we compressed was treated, by image index.
+    textures: HashMap<usize, TextureOutcome>,
+    /// Images that couldn't be compressed and were left as they were, with
+    /// `--keep-going`.
+    failures: Vec<report::TextureFailure>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TextureOutcome {
+    texture_type: TextureType,
+    cached: bool,
+    quality: Option<quality::Metrics>,
+}
+
+impl Input {
+    /// Returns the bytes that a buffer view points to.
+    fn view_data(&self, view: &gltf::buffer::View) -> &[u8] {
+        let buffer = &self.buffers[view.buffer().index()];
+        &buffer[view.offset()..view.offset() + view.length()]
+    }
+
+    /// Parses a GLB file that's already in memory.
+    fn from_glb(bytes: &[u8], base: PathBuf) -> anyhow::Result<Self> {
+        let glb = gltf::Glb::from_slice(bytes).context("unable to parse GLB file")?;
+        let (document, blob) = parse_glb(glb)?;
+        let buffers = load_buffers(&document, &base, blob)?;
+
+        Ok(Input {
+            document,
+            buffers,
+            base,
+        })
+    }
+
+    /// Returns the contents of an image and its MIME type, if it has one or
+    /// it can be worked out from the image's URI.
+    fn image_data<'a>(
+        &'a self,
+        image: &gltf::Image<'a>,
+    ) -> anyhow::Result<(Cow<'a, [u8]>, Option<&'a str>)> {
+        match image.source() {
+            gltf::image::Source::View { view, mime_type } => {
+                Ok((Cow::Borrowed(self.view_data(&view)), Some(mime_type)))
+            }
+            gltf::image::Source::Uri { uri, mime_type } => {
+                let bytes = read_uri(&self.base, uri)
+                    .with_context(|| format!("failed to load image at URI {uri}"))?;
+                Ok((Cow::Owned(bytes), mime_type.or_else(|| uri_mime_type(uri))))
+            }
+        }
+    }
+
+    /// Makes changes to the document's JSON, then checks that the result is
+    /// still a valid document.
+    fn edit_json(
+        self,
+        edit: impl FnOnce(&mut gltf::json::Root, &[Vec<u8>], &Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Self> {
+        let mut root = self.document.into_json();
+        edit(&mut root, &self.buffers, &self.base)?;
+        let document = gltf::Document::from_json(root).context("edited document is invalid")?;
+
+        Ok(Input {
+            document,
+            buffers: self.buffers,
+            base: self.base,
+        })
+    }
+}
+
+/// Which part of the glTF material model this texture is.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum TextureType {
+    BaseColor,
+    Normal,
+    MetallicRoughnessOcclusion,
+    Emissive,
+}
+
+impl TextureType {
+    const ALL: [TextureType; 4] = [
+        TextureType::BaseColor,
+        TextureType::Normal,
+        TextureType::MetallicRoughnessOcclusion,
+        TextureType::Emissive,
+    ];
+
+    pub fn is_srgb(&self) -> bool {
+        matches!(self, TextureType::BaseColor | TextureType::Emissive)
+    }
+
+    pub fn block_size(&self) -> &'static str {
+        match self {
+            // TextureType::MetallicRoughnessOcclusion => command.arg("6x6"),
+            // TextureType::Emissive => command.arg("10x10"),
+            TextureType::BaseColor | TextureType::Emissive => "6x6",
+            _ => "4x4",
+        }
+    }
+}
+
+impl FromStr for TextureType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base-color" => Ok(Self::BaseColor),
+            "normal" => Ok(Self::Normal),
+            "metallic-roughness-occlusion" => Ok(Self::MetallicRoughnessOcclusion),
+            "emissive" => Ok(Self::Emissive),
+            _ => bail!(
+                "unknown texture type '{s}', expected 'base-color', 'normal', \
+                'metallic-roughness-occlusion' or 'emissive'"
+            ),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TextureType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn squish(args: Args) -> anyhow::Result<()> {
+    match args.progress {
+        Some(_) => progress::configure_logging(args.verbose),
+        None => configure_logging(args.verbose),
+    }
+
+    // Every target, and every run when watching, shares the work of decoding
+    // and resizing the images.
+    let session = Session::default();
+    if !args.watch {
+        return squish_targets(&args, &session).and_then(|outputs| check_failures(&outputs));
+    }
+
+    watch::watch(&args, || {
+        let result = squish_targets(&args, &session);
+        session.sources.forget_unused();
+        check_failures(&result?)
+    })
+}
+
+/// What's kept from one squish to the next, when watching or serving.
+#[derive(Default)]
+struct Session {
+    /// Decoded and resized images, shared by every target and every run.
+    sources: Rc<sources::Sources>,
+    /// A `toktx` that's already been checked, rather than finding it again
+    /// for every run. Runs whose config picks a different one still find it.
+    toktx: Option<Rc<toktx::Toktx>>,
+    /// The caches that have been used, so they're only listed once.
+    caches: Arc<cache::Caches>,
+    /// Set from another thread to stop squishing before the next texture.
+    cancelled: Arc<AtomicBool>,
+}
+
+/// An output that was written, and its size report.
+#[derive(Debug)]
+struct Output {
+    path: PathBuf,
+    report: report::SizeReport,
+}
+
+/// Fails if `--keep-going` had to leave any images uncompressed, so the
+/// process exits with its own status.
+fn check_failures(outputs: &[Output]) -> anyhow::Result<()> {
+    let count: usize = outputs.iter().map(|o| o.report.failures.len()).sum();
+    if count > 0 {
+        return Err(SquishError::Partial { count }.into());
+    }
+    Ok(())
+}
+
+/// Squishes the input into an output for each profile, or just the one if
+/// there aren't any.
+fn squish_targets(args: &Args, session: &Session) -> anyhow::Result<Vec<Output>> {
+    let needed = !args.profile.is_empty();
+    let config = config::load_config(&args.input, args.config.as_deref(), needed)?;
+    let targets = if args.profile.is_empty() {
+        vec![(None, config::Profile::default())]
+    } else {
+        let config = config.as_ref().with_context(|| {
+            format!(
+                "no {} found in the directories above {}",
+                config::FILE_NAME,
+                args.input.display()
+            )
+        })?;
+        args.profile
+            .iter()
+            .map(|name| Ok((Some(name.as_str()), config.profile(name)?.clone())))
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    log::info!("Squishing {}", args.input.display());
+    let input = open(&args.input)?;
+
+    let configured = config.as_ref().and_then(|config| config.toktx.as_deref());
+    let timeout = Duration::from_secs(args.toktx_timeout);
+    let toktx = match &session.toktx {
+        // A server's requests can each have their own config and timeout.
+        Some(toktx) if toktx.is_found_from(configured) => match toktx.timeout() == timeout {
+            true => toktx.clone(),
+            false => Rc::new(toktx.with_timeout(timeout)),
+        },
+        _ => {
+            let toktx =
+                toktx::Toktx::find(configured, timeout).context(SquishError::ToktxUnavailable)?;
+            Rc::new(toktx)
+        }
+    };
+
+    let output_paths: Vec<_> = targets
+        .iter()
+        .map(|(name, _)| target_path(&args.output, name.filter(|_| targets.len() > 1)))
+        .collect();
+    let dependencies = deps::dependencies(args, &input);
+    let fingerprint = match &args.stamp {
+        Some(stamp) => {
+            let fingerprint = deps::fingerprint(args, &toktx, &dependencies)?;
+            // The size reports are outputs too, and skipping would leave
+            // missing ones unwritten.
+            let reports = args.report.iter().flat_map(|report| {
+                targets
+                    .iter()
+                    .map(|(name, _)| target_path(report, name.filter(|_| targets.len() > 1)))
+            });
+            let written: Vec<_> = output_paths.iter().cloned().chain(reports).collect();
+            if deps::is_up_to_date(stamp, &fingerprint, &written)? {
+                log::info!("Nothing has changed since the last squish, skipping it");
+                // Bring the depfile and stamp up to date too, for build
+                // systems that check their times.
+                if let Some(depfile) = &args.depfile {
+                    deps::write_depfile(depfile, &output_paths, &dependencies)?;
+                }
+                deps::write_stamp(stamp, &fingerprint)?;
+                return Ok(Vec::new());
+            }
+            Some(fingerprint)
+        }
+        None => None,
+    };
+
+    let original = inspect::Report::new(&input)?;
+    // A .gltf is only part of the model, so count its buffers and images
+    // too. Missing images are left for compressing them to report.
+    let input_len = deps::model_files(&args.input, &input)
+        .iter()
+        .filter_map(|path| fs_err::metadata(path).ok())
+        .map(|metadata| metadata.len() as usize)
+        .sum();
+
+    let sidecar = Rc::new(overrides::Sidecar::load(&args.input)?);
+    let cache = match args.no_cache {
+        true => None,
+        false => {
+            let settings = config
+                .as_ref()
+                .map(|config| config.cache.clone())
+                .unwrap_or_default();
+            Some(Rc::new(session.caches.open(&settings)?))
+        }
+    };
+
+    // Every target starts from the same parsed input.
+    let mut outputs = Vec::new();
+    for (name, profile) in &targets {
+        let name = name.filter(|_| targets.len() > 1);
+        if let Some(name) = name {
+            log::info!("Squishing for profile '{name}'");
+        }
+
+        let target = Target {
+            args,
+            profile,
+            name,
+            original: &original,
+            input_len,
+            sources: session.sources.clone(),
+            cancelled: session.cancelled.clone(),
+            sidecar: sidecar.clone(),
+            cache: cache.clone(),
+            toktx: toktx.clone(),
+        };
+        outputs.push(target.squish(input.clone())?);
+    }
+
+    for key in sidecar.unused_keys(&input) {
+        log::warn!("Texture overrides for '{key}' don't match any image");
+    }
+
+    if let Some(cache) = cache {
+        cache.shrink().with_context(|| SquishError::Cache {
+            dir: cache.dir().to_path_buf(),
+        })?;
+    }
+
+    if let Some(depfile) = &args.depfile {
+        deps::write_depfile(depfile, &output_paths, &dependencies)?;
+    }
+    // Images left uncompressed by --keep-going should be tried again next
+    // time, so there's no stamp for a partial squish.
+    let complete = outputs.iter().all(|o| o.report.failures.is_empty());
+    if let (Some(stamp), Some(fingerprint), true) = (&args.stamp, &fingerprint, complete) {
+        deps::write_stamp(stamp, fingerprint)?;
+    }
+
+    Ok(outputs)
+}
+
+/// One output to squish the input into.
+struct Target<'a> {
+    args: &'a Args,
+    profile: &'a config::Profile,
+    /// The profile name to put in the output paths, if there's more than one
+    /// output.
+    name: Option<&'a str>,
+    original: &'a inspect::Report,
+    input_len: usize,
+    sources: Rc<sources::Sources>,
+    cancelled: Arc<AtomicBool>,
+    sidecar: Rc<overrides::Sidecar>,
+    cache: Option<Rc<cache::Cache>>,
+    toktx: Rc<toktx::Toktx>,
+}
+
+impl Target<'_> {
+    /// Squishes the input and writes the output.
+    fn squish(&self, mut input: Input) -> anyhow::Result<Output> {
+        let (args, profile) = (self.args, self.profile);
+        let started = Instant::now();
+        let output_path = target_path(&args.output, self.name);
+        let progress = progress::Progress::new(args.progress);
+        progress.emit(progress::Event::FileStarted {
+            input: &args.input,
+            output: &output_path,
+            profile: self.name,
+        });
+
+        if !args.no_prune && profile.prune.unwrap_or(true) {
+            input = input.edit_json(|root, _, _| {
+                prune::prune(root);
+                Ok(())
+            })?;
+        }
+        if !args.no_dedup && profile.dedup.unwrap_or(true) {
+            input = input.edit_json(dedup::dedup)?;
+        }
+
+        let before = inspect::Report::new(&input)?;
+        let context = SquishContext {
+            input,
+            cache: self.cache.clone(),
+            toktx: self.toktx.clone(),
+            use_supercompression: !args.no_supercompression
+                && profile.supercompression.unwrap_or(true),
+            allow_invalid: args.allow_invalid,
+            keep_going: args.keep_going,
+            max_size: args.max_size.or(profile.max_size).unwrap_or(MAX_SIZE),
+            textures: texture_settings(args, profile),
+            sources: self.sources.clone(),
+            cancelled: self.cancelled.clone(),
+            sidecar: self.sidecar.clone(),
+            progress,
+        };
+
+        let squished = context.optimize()?;
+
+        let output = Input::from_glb(&squished.glb, PathBuf::new())?;
+        let after = inspect::Report::new(&output)?;
+        let report = report::SizeReport::new(
+            self.original,
+            &before,
+            &after,
+            &squished.textures,
+            &squished.failures,
+            self.input_len,
+            squished.glb.len(),
+        );
+        log::info!("{report}");
+        if let Some(path) = &args.report {
+            let json = gltf::json::serialize::to_string_pretty(&report)?;
+            fs_err::write(target_path(path, self.name), json)
+                .context("failed to write size report")?;
+        }
+
+        let budgets = budget::Budgets {
+            max_file_size: args.max_file_size,
+            max_gpu_memory: args.max_gpu_memory,
+            max_texture_size: args.max_texture_size,
+        };
+        budgets.check(&after, squished.glb.len())?;
+
+        fs_err::write(&output_path, &squished.glb)?;
+
+        log::info!("Squished file: {}! ✨ Enjoy ✨", output_path.display());
+        progress.emit(progress::Event::FileFinished {
+            output: &output_path,
+            input_bytes: self.input_len,
+            output_bytes: squished.glb.len(),
+            seconds: started.elapsed().as_secs_f64(),
+        });
+        Ok(Output {
+            path: output_path,
+            report,
+        })
+    }
+}
+
+/// Puts a profile name before the extension of a path, so each target gets
+/// its own file: `model.glb` becomes `model.quest2.glb`.
+fn target_path(path: &Path, name: Option<&str>) -> PathBuf {
+    let Some(name) = name else {
+        return path.to_path_buf();
+    };
+
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(name);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+fn configure_logging(verbose: bool) {
+    // If logging is already configured (like running in a test), we should
+    // suppress any issues initializing it.
+    let _ = log_builder(verbose).try_init();
+}
+
+fn log_builder(verbose: bool) -> env_logger::Builder {
+    let filter = if verbose {
+        "squisher=debug,warn"
+    } else {
+        "squisher=info,warn"
+    };
+
+    let log_env = env_logger::Env::default().default_filter_or(filter);
+    let mut builder = env_logger::Builder::from_env(log_env);
+    builder.format_timestamp(None);
+    builder
+}
+
+/// Works out how to compress each texture type from the command line, the
+/// profile and the defaults, in that order.
+fn texture_settings(
+    args: &Args,
+    profile: &config::Profile,
+) -> HashMap<TextureType, TextureSettings> {
+    let auto_block_size = args.auto_block_size || profile.auto_block_size.unwrap_or(false);
+
+    TextureType::ALL
+        .into_iter()
+        .map(|texture_type| {
+            let format = args
+                .format
+                .or_else(|| profile.formats.get(&texture_type).copied())
+                .unwrap_or(TextureFormat::Astc);
+            let block_size = profile
+                .block_sizes
+                .get(&texture_type)
+                .and_then(|size| BLOCK_SIZES.iter().find(|s| *s == size))
+                .copied()
+                .unwrap_or_else(|| texture_type.block_size());
+
+            let quality_target = (auto_block_size && format == TextureFormat::Astc).then(|| {
+                let mut target = quality::QualityTarget::default_for(texture_type);
+                if let Some(threshold) = profile.quality.get(&texture_type) {
+                    target.threshold = *threshold;
+                }
+                for overridden in &args.quality_target {
+                    if overridden.texture_type == texture_type {
+                        target = *overridden;
+                    }
+                }
+                target
+            });
+
+            let settings = TextureSettings {
+                format,
+                block_size,
+                quality_target,
+            };
+            (texture_type, settings)
+        })
+        .collect()
+}
+
+impl SquishContext {
+    fn optimize(self) -> anyhow::Result<Squished> {
+        // Ensure our cache directory exists and is ready to use
+        if let Some(cache) = &self.cache {
+            fs_err::create_dir_all(cache.dir()).with_context(|| SquishError::Cache {
+                dir: cache.dir().to_path_buf(),
+            })?;
+        }
+
+        let mut image_map: HashMap<usize, CompressedTexture> = Default::default();
+
+        // First, compress the images.
+        // In order to do this, we need to have a bit of information about them first:
+        let document = &self.input.document;
+        let mut queue = Vec::new();
+        for material in document.materials() {
+            // Okiedokie. Each part of the material needs to be treated differently.
+            let pbr = material.pbr_metallic_roughness();
+            let slots = [
+                (
+                    pbr.base_color_texture().map(|info| info.texture()),
+                    TextureType::BaseColor,
+                ),
+                (
+                    pbr.metallic_roughness_texture().map(|info| info.texture()),
+                    TextureType::MetallicRoughnessOcclusion,
+                ),
+                (
+                    material.normal_texture().map(|info| info.texture()),
+                    TextureType::Normal,
+                ),
+                (
+                    material.emissive_texture().map(|info| info.texture()),
+                    TextureType::Emissive,
+                ),
+                (
+                    material.occlusion_texture().map(|info| info.texture()),
+                    TextureType::MetallicRoughnessOcclusion,
+                ),
+            ];
+            for (texture, texture_type) in slots {
+                if let Some(texture) = texture {
+                    queue.push((material.clone(), texture, texture_type));
+                }
+            }
+        }
+
+        for (_, texture, texture_type) in &queue {
+            let texture_type = format!("{texture_type:?}");
+            self.progress.emit(progress::Event::TextureQueued {
+                texture: progress_texture(texture, &texture_type),
+            });
+        }
+
+        let mut failures: Vec<report::TextureFailure> = Vec::new();
+        for (material, texture, texture_type) in queue {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Err(SquishError::Cancelled.into());
+            }
+
+            let index = texture.source().index();
+            match self.compress_texture(&material, &texture, texture_type) {
+                Ok(Some(compressed)) => {
+                    image_map.insert(index, compressed);
+                }
+                Ok(None) => {}
+                // Leave the image as it is, so the rest can still be squished.
+                Err(err) if self.keep_going => {
+                    log::warn!("Leaving image {index} uncompressed: {err:#}");
+                    let texture_type = format!("{texture_type:?}");
+                    self.progress.emit(progress::Event::TextureFailed {
+                        texture: progress_texture(&texture, &texture_type),
+                        error: format!("{err:#}"),
+                    });
+                    if !failures.iter().any(|failure| failure.index == index) {
+                        failures.push(report::TextureFailure {
+                            index,
+                            name: texture.source().name().map(str::to_string),
+                            texture_type,
+                            error: format!("{err:#}"),
+                        });
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // An image used by several materials might have worked another time.
+        failures.retain(|failure| !image_map.contains_key(&failure.index));
+
+        // Okay. Now that's done we need a new GLB file.
+        let glb = self.create_glb_file(&image_map)?;
+        let textures = image_map
+            .into_iter()
+            .map(|(index, texture)| {
+                let outcome = TextureOutcome {
+                    texture_type: texture.texture_type,
+                    cached: texture.cached,
+                    quality: texture.quality,
+                };
+                (index, outcome)
+            })
+            .collect();
+
+        Ok(Squished {
+            glb,
+            textures,
+            failures,
+        })
+    }
+
+    fn compress_texture(
+        &self,
+        material: &gltf::Material,
+        texture: &gltf::Texture,
+        texture_type: TextureType,
+    ) -> anyhow::Result<Option<CompressedTexture>> {
+        let unsupported = |feature: String| SquishError::Unsupported {
+            feature,
+            image: Some(Object::image(&texture.source())),
+        };
+        let encode = || SquishError::Encode {
+            image: Object::image(&texture.source()),
+            material: Object::material(material),
+            texture_type,
+        };
+
+        let (bytes, mime_type) = match texture.source().source() {
+            gltf::image::Source::View { view, mime_type } => {
+                (Cow::Borrowed(self.input.view_data(&view)), mime_type)
+            }
+            gltf::image::Source::Uri { uri, mime_type } => {
+                let bytes = read_uri(&self.input.base, uri)
+                    .with_context(|| format!("failed to load image at URI {uri}"))
+                    .map_err(|e| encode().or_existing(e))?;
+                let mime_type = mime_type
+                    .or_else(|| uri_mime_type(uri))
+                    .ok_or_else(|| unsupported("an image without a known MIME type".into()))?;
+
+                (Cow::Owned(bytes), mime_type)
+            }
+        };
+
+        let type_name = format!("{texture_type:?}");
+        let event_texture = progress_texture(texture, &type_name);
+        let format = match mime_type {
+            "image/jpeg" => image::ImageFormat::Jpeg,
+            "image/png" => image::ImageFormat::Png,
+            "image/ktx2" => {
+                self.progress.emit(progress::Event::TextureSkipped {
+                    texture: event_texture,
+                });
+                return Ok(None);
+            }
+            _ => return Err(unsupported(format!("image MIME type {mime_type}")).into()),
+        };
+
+        let index = texture.source().index();
+        log::debug!(
+            "Image {index} has content hash {}",
+            overrides::content_hash(&bytes)
+        );
+        let overrides = self.sidecar.overrides(material, texture, &bytes)?;
+        if overrides.skip == Some(true) {
+            log::info!("Leaving image {index} as it is, as its overrides asked");
+            self.progress.emit(progress::Event::TextureSkipped {
+                texture: event_texture,
+            });
+            return Ok(None);
+        }
+        let started = Instant::now();
+        self.progress.emit(progress::Event::TextureStarted {
+            texture: event_texture,
+        });
+        let settings = overrides.apply(self.textures[&texture_type], texture_type);
+        let max_size = overrides.max_size.unwrap_or(self.max_size);
+        log::info!(
+            "Compressing {texture_type:?} as format {:?}...",
+            settings.format
+        );
+
+        let cache_name = file_name(
+            &self.toktx,
+            texture_type,
+            &settings,
+            self.use_supercompression,
+            max_size,
+            &bytes,
+        );
+
+        // If this file already exists, that means that we already hashed this
+        // image with the same configuration. We can just slurp it up and return
+        // here!
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&cache_name));
+        if let Some((file, manifest)) = cached {
+            log::info!("Returning pre-compressed file!");
+            // Files cached by older versions still say which toktx wrote them,
+            // and might not have been measured.
+            let file = reproducible::strip_writer(&file).with_context(encode)?;
+            let quality = manifest
+                .quality
+                .or_else(|| self.measure_quality(&bytes, &file, texture_type, settings.format));
+            self.progress.emit(progress::Event::TextureCached {
+                texture: event_texture,
+                bytes: file.len(),
+                seconds: started.elapsed().as_secs_f64(),
+            });
+
+            return Ok(Some(CompressedTexture {
+                quality,
+                bytes: file,
+                texture_type,
+                cached: true,
+            }));
+        }
+
+        // If the image is too big, it's resized and re-encoded before being
+        // passed onto `toktx`.
+        let resized = self
+            .sources
+            .resized(&bytes, format, max_size)
+            .with_context(encode)?;
+        let bytes = resized.as_deref().map_or(&*bytes, Vec::as_slice);
+
+        // Pipe the bytes through toktx, giving us spiffy KTX2 image bytes.
+        let (block_size, output) = match settings.quality_target {
+            Some(target) => {
+                let source = self.sources.decoded(bytes).with_context(encode)?;
+                choose_block_size(
+                    &self.toktx,
+                    bytes,
+                    &source,
+                    target,
+                    self.use_supercompression,
+                )
+                .with_context(encode)?
+            }
+            None => {
+                let output = toktx(
+                    &self.toktx,
+                    bytes,
+                    settings.format,
+                    texture_type,
+                    settings.block_size,
+                    self.use_supercompression,
+                )
+                .with_context(encode)?;
+                (settings.block_size, output)
+            }
+        };
+        let output = reproducible::strip_writer(&output).with_context(encode)?;
+        let quality = self.measure_quality(bytes, &output, texture_type, settings.format);
+
+        if let Some(cache) = &self.cache {
+            let manifest = cache::Manifest {
+                texture_type: format!("{texture_type:?}"),
+                format: format!("{:?}", settings.format),
+                block_size: (settings.format == TextureFormat::Astc).then(|| block_size.into()),
+                quality_target: settings.quality_target.map(|target| target.threshold),
+                supercompression: self.use_supercompression,
+                max_size,
+                toktx: self.toktx.version().to_string(),
+                quality,
+            };
+            cache
+                .put(&cache_name, &output, &manifest)
+                .with_context(|| SquishError::Cache {
+                    dir: cache.dir().to_path_buf(),
+                })?;
+        }
+        self.progress.emit(progress::Event::TextureFinished {
+            texture: event_texture,
+            bytes: output.len(),
+            seconds: started.elapsed().as_secs_f64(),
+        });
+
+        Ok(Some(CompressedTexture {
+            quality,
+            bytes: output,
+            texture_type,
+            cached: false,
+        }))
+    }
+
+    /// Compares a compressed texture to its source, logging why if that
+    /// isn't possible rather than failing the whole squish.
+    fn measure_quality(
+        &self,
+        source: &[u8],
+        compressed: &[u8],
+        texture_type: TextureType,
+        format: TextureFormat,
+    ) -> Option<quality::Metrics> {
+        let packed_normals = format.packs_normal_maps();
+        self.sources
+            .decoded(source)
+            .and_then(|source| {
+                quality::Metrics::measure(&source, compressed, texture_type, packed_normals)
+            })
+            .map_err(|e| {
+                log::warn!("Unable to measure the quality of a {texture_type:?} texture: {e:#}")
+            })
+            .ok()
+    }
+
+    fn create_glb_file(
+        self,
+        image_map: &HashMap<usize, CompressedTexture>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let allow_invalid = self.allow_invalid;
+        let generator = format!(
+            "squisher {} with toktx {}",
+            env!("CARGO_PKG_VERSION"),
+            self.toktx.version()
+        );
+        let images = image_map
+            .iter()
+            .map(|(index, texture)| (*index, (texture.bytes.as_slice(), "image/ktx2")))
+            .collect();
+        let (mut new_root, new_blob) = pack_buffers(self.input, &images)?;
+
+        // Record what made the textures, so it's possible to tell which
+        // toktx a problem texture came from.
+        new_root.asset.generator = Some(generator);
+
+        // Before writing anything, make sure loaders will accept what we made.
+        let issues = validate::validate(&new_root, &new_blob);
+        if !issues.is_empty() {
+            if !allow_invalid {
+                return Err(SquishError::Validation { issues }.into());
+            }
+            let report: String = issues.iter().map(|issue| format!("\n  {issue}")).collect();
+            log::warn!("Output failed validation:{report}");
+        }
+
+        to_glb(&new_root, new_blob)
+    }
+}
+
+/// Which texture a progress event is about.
+fn progress_texture<'a>(
+    texture: &gltf::Texture<'a>,
+    texture_type: &'a str,
+) -> progress::Texture<'a> {
+    let image = texture.source();
+    progress::Texture {
+        image: image.index(),
+        name: image.name(),
+        texture_type,
+    }
+}
+
+/// Rebuilds a document so that all of its data, including its images, lives
+/// in a single buffer that can go in a GLB's BIN chunk. The given images have
+/// their contents and MIME type replaced.
+fn pack_buffers(
+    input: Input,
+    images: &HashMap<usize, (&[u8], &str)>,
+) -> anyhow::Result<(gltf::json::Root, Vec<u8>)> {
+    // Ugh, this is going to be disgusting.
+    let mut new_blob: Vec<u8> = Vec::new();
+    let buffers = input.buffers;
+    let base = input.base;
+    let mut new_buffer_views: Vec<gltf::json::buffer::View> = Vec::new();
+    let mut new_root = input.document.into_json();
+
+    // First, we need to make a map that lets us find which image a bufferView points to, if any.
+    let mut image_buffer_view_indices = HashMap::new();
+    for (index, image) in new_root.images.iter().enumerate() {
+        if let Some(image_view_index) = image.buffer_view {
+            image_buffer_view_indices.insert(image_view_index.value(), index);
+        }
+    }
+
+    // Next, go through each buffer view and write its data into our blob.
+    // Views may point into any of the input buffers, but they all end up
+    // in the single buffer stored in the GLB's BIN chunk.
+    for (index, view) in new_root.buffer_views.iter_mut().enumerate() {
+        // Keep every view aligned, as accessors into it may require it.
+        pad_byte_vector(&mut new_blob);
+
+        // Stash the CURRENT length (eg before we add to it) of the new blob
+        let new_offset = new_blob.len();
+
+        // Okay, this buffer view points to an image - we instead want to
+        // grab the bytes of the compressed image.
+        let bytes = image_buffer_view_indices
+            .get(&index)
+            .and_then(|image_index| images.get(image_index))
+            .map(|(bytes, _)| *bytes)
+            .unwrap_or_else(|| {
+                // This is either not an image or is an image that isn't
+                // part of the material model we support — just get the
+                // original data and return it as-is.
+                let start = view.byte_offset.unwrap_or_default() as usize;
+                let end = start + view.byte_length as usize;
+                &buffers[view.buffer.value()][start..end]
+            });
+
+        // And write it into the new blob.
+        new_blob.extend_from_slice(bytes);
+
+        // Now create a new view and change its offset to reflect the new blob.
+        let mut new_view = view.clone();
+        new_view.buffer = Index::new(0);
+        new_view.byte_offset = Some(new_offset as _);
+        new_view.byte_length = bytes.len() as _;
+        new_buffer_views.push(new_view);
+    }
+
+    // OK. Now we need to update any images that had their uri set (bufferView and uri are mutually exclusive)
+    for (index, image) in new_root.images.iter_mut().enumerate() {
+        // This image has already been processed, we can move on.
+        let Some(uri) = image.uri.take() else {
+            // Set the MIME type, if we replaced it
+            if let Some((_, mime_type)) = images.get(&index) {
+                image.mime_type = Some(MimeType(mime_type.to_string()));
+            }
+            continue;
+        };
+
+        // Right. As before, stash the current length of the new blob
+        pad_byte_vector(&mut new_blob);
+        let new_offset = new_blob.len();
+
+        // Get the current length of the buffer views to use as an index
+        let buffer_view_index = new_buffer_views.len();
+
+        // Now write the new image data into the blob. Images that weren't
+        // replaced are embedded as-is, so they keep their MIME type.
+        let image_data = match images.get(&index) {
+            Some((bytes, mime_type)) => {
+                image.mime_type = Some(MimeType(mime_type.to_string()));
+                Cow::Borrowed(*bytes)
+            }
+            None => {
+                let mime_type = image
+                    .mime_type
+                    .as_ref()
+                    .map(|m| m.0.as_str())
+                    .or_else(|| uri_mime_type(&uri))
+                    .with_context(|| format!("unable to determine MIME type of image {uri}"))?;
+                image.mime_type = Some(MimeType(mime_type.to_string()));
+                Cow::Owned(
+                    read_uri(&base, &uri)
+                        .with_context(|| format!("failed to load image at URI {uri}"))?,
+                )
+            }
+        };
+        new_blob.extend_from_slice(&image_data);
+
+        // Create a new buffer view for this image
+        let view = gltf::json::buffer::View {
+            buffer: Index::new(0 as _),
+            byte_length: image_data.len() as _,
+            byte_offset: Some(new_offset as _),
+            byte_stride: None,
+            name: None,
+            target: None,
+            extensions: None,
+            extras: Default::default(),
+        };
+
+        // And add it to the list
+        new_buffer_views.push(view);
+
+        // Finally, update the image to point to this new view.
+        image.buffer_view = Some(Index::new(buffer_view_index as _));
+    }
+
+    // OK! We're done. Set the new root to use the new buffer views..
+    new_root.buffer_views = new_buffer_views;
+
+    // And make sure the buffer is set correctly.
+    new_root.buffers = vec![gltf::json::Buffer {
+        byte_length: new_blob.len() as _,
+        name: None,
+        uri: None,
+        extensions: None,
+        extras: Default::default(),
+    }];
+
+    // and.. that's it? Maybe? Hopefully.
+    // This part is mostly lifted from https://github.com/gltf-rs/gltf/blob/master/examples/export/main.rs
+
+    pad_byte_vector(&mut new_blob);
+
+    Ok((new_root, new_blob))
+}
+
+/// Writes a document and the contents of its only buffer out as a GLB file.
+fn to_glb(new_root: &gltf::json::Root, new_blob: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let buffer_length = new_blob.len() as u32;
+    let json_string = reproducible::to_json(new_root)?;
+    let mut json_offset = json_string.len() as u32;
+    align_to_multiple_of_four(&mut json_offset);
+
+    let glb = gltf::binary::Glb {
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            length: json_offset + buffer_length,
+        },
+        bin: Some(Cow::Owned(new_blob)),
+        json: Cow::Owned(json_string.into_bytes()),
+    };
+
+    // And we're done! Write the entire file to GLB.
+    Ok(glb.to_vec()?)
+}
+
+fn align_to_multiple_of_four(n: &mut u32) {
+    *n = (*n + 3) & !3;
+}
+
+/// Pads the length of a byte vector to a multiple of four bytes.
+fn pad_byte_vector(vec: &mut Vec<u8>) {
+    while vec.len() % 4 != 0 {
+        vec.push(0);
+    }
+}
+
+/// The ASTC block sizes worth trying, from the smallest output to the best
+/// quality.
+const BLOCK_SIZES: [&str; 6] = ["12x12", "10x10", "8x8", "6x6", "5x5", "4x4"];
+
+/// Compresses an image with each ASTC block size in turn, returning the
+/// largest block size that meets the quality target along with the
+/// compressed image.
+fn choose_block_size(
+    encoder: &toktx::Toktx,
+    input_bytes: &[u8],
+    source: &image::RgbaImage,
+    target: quality::QualityTarget,
+    use_supercompression: bool,
+) -> anyhow::Result<(&'static str, Vec<u8>)> {
+    let mut output = Vec::new();
+    for block_size in BLOCK_SIZES {
+        output = toktx(
+            encoder,
+            input_bytes,
+            TextureFormat::Astc,
+            target.texture_type,
+            block_size,
+            use_supercompression,
+        )
+        .with_context(|| format!("failed to compress with {block_size} blocks"))?;
+        let compressed = quality::decode_ktx2(&output)
+            .with_context(|| format!("failed to decode {block_size} ASTC output"))?;
+
+        if target.is_met(source, &compressed) {
+            log::info!("Using {block_size} blocks for {:?}", target.texture_type);
+            return Ok((block_size, output));
+        }
+        log::debug!("{block_size} blocks don't meet the quality target");
+    }
+
+    let smallest = BLOCK_SIZES[BLOCK_SIZES.len() - 1];
+    log::warn!(
+        "No block size meets the quality target for {:?}, using {smallest}",
+        target.texture_type
+    );
+    Ok((smallest, output))
+}
+
+fn toktx(
+    encoder: &toktx::Toktx,
+    input_bytes: &[u8],
+    format: TextureFormat,
+    texture_type: TextureType,
+    block_size: &str,
+    supercompress: bool,
+) -> anyhow::Result<Vec<u8>> {
+    // Create a temporary file to put our image data into, as `toktx` can't
+    // read images from stdin.
+    let dir = tempfile::tempdir()?;
+    let input_path = dir.path().join("input");
+    fs_err::write(&input_path, input_bytes).context("failed to write to temporary file")?;
+
+    let mut command = encoder.command();
+    command.args([
+        "--t2",        // Use KTX2 instead of KTX.
+        "--genmipmap", // Generate mipmaps.
+    ]);
+
+    if supercompress {
+        // Compress with Zstandard, quality 20.
+        command.args(["--zcmp", "20"]);
+    }
+
+    match format {
+        TextureFormat::Rgba8 => {
+            command.args(["--target_type", "RGBA"]);
+        }
+        TextureFormat::Astc => {
+            command.args(["--encode", "astc", "--astc_blk_d"]);
+            command.arg(block_size);
+            command.args(["--astc_quality", "thorough"]);
+        }
+    }
+
+    if texture_type == TextureType::Normal {
+        // Generate a normalized normal map.
+        command.args(["--normal_mode", "--normalize"]);
+    }
+
+    // Embed the correct color space into the output.
+    command.arg("--assign_oetf");
+    if texture_type.is_srgb() {
+        command.arg("srgb");
+    } else {
+        command.arg("linear");
+    }
+
+    // Write the result to stdout instead of to a file.
+    command.arg("-");
+
+    // Use our temporary file as the input.
+    command.arg(input_path);
+
+    log::debug!(
+        "Running toktx {} with args {:?}",
+        encoder.version(),
+        command.get_args().collect::<Vec<_>>()
+    );
+
+    encoder.run(&mut command)
+}
+
+// Generates a file name suitable for caching a KTX2 file generated from the
+// given inputs.
+fn file_name(
+    toktx: &toktx::Toktx,
+    texture_type: TextureType,
+    settings: &TextureSettings,
+    supercompress: bool,
+    max_size: u32,
+    file_bytes: &[u8],
+) -> String {
+    let mut hasher = seahash::SeaHasher::new();
+    hasher.write(toktx.version().as_bytes());
+    hasher.write_u8(texture_type as _);
+    hasher.write_u8(settings.format as _);
+    hasher.write_u8(supercompress as _);
+    hasher.write_u32(max_size);
+    match settings.quality_target {
+        Some(target) => hasher.write_u64(target.threshold.to_bits()),
+        None => hasher.write(settings.block_size.as_bytes()),
+    }
+    hasher.write(file_bytes);
+    let hash = hasher.finish();
+
+    // Format the file as 16 hexadecimal digits so that all files have a name
+    // with the same length.
+    format!("{:016X}", hash)
+}
+
+fn open(path: &Path) -> anyhow::Result<Input> {
+    let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let (document, blob) = parse_input(path).with_context(|| SquishError::InvalidInput {
+        path: path.to_path_buf(),
+    })?;
+    let buffers = load_buffers(&document, &base, blob).map_err(|e| {
+        SquishError::InvalidInput {
+            path: path.to_path_buf(),
+        }
+        .or_existing(e)
+    })?;
+
+    Ok(Input {
+        document,
+        buffers,
+        base,
+    })
+}
+
+/// Parses a glTF or GLB file, without loading its buffers.
+fn parse_input(path: &Path) -> anyhow::Result<(gltf::Document, Option<Vec<u8>>)> {
+    let reader = fs_err::File::open(path)?;
+    let parsed = match path.extension().and_then(|s| s.to_str()) {
+        Some("gltf") => {
+            let json =
+                gltf::json::Root::from_reader(reader).context("unable to parse glTF file")?;
+            let document = gltf::Document::from_json(json).context("invalid glTF file")?;
+
+            (document, None)
+        }
+        Some("glb") => {
+            let glb = gltf::Glb::from_reader(reader).context("unable to parse GLB file")?;
+            parse_glb(glb)?
+        }
+        _ => {
+            bail!(
+                "File does not have extension gltf or glb: {}",
+                path.display()
+            );
+        }
+    };
+
+    Ok(parsed)
+}
+
+fn parse_glb(glb: gltf::Glb) -> anyhow::Result<(gltf::Document, Option<Vec<u8>>)> {
+    let json = gltf::json::Root::from_slice(&glb.json)?;
+    let document = gltf::Document::from_json(json).context("invalid JSON in GLB file")?;
+
+    Ok((document, glb.bin.map(Cow::into_owned)))
+}
+
+/// Loads the contents of every buffer in the document, whether it lives in
+/// the GLB's BIN chunk, in an external file or in a data URI.
+fn load_buffers(
+    document: &gltf::Document,
+    base: &Path,
+    mut blob: Option<Vec<u8>>,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    document
+        .buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => blob.take().context("no data in GLB file")?,
+                gltf::buffer::Source::Uri(uri) => read_uri(base, uri)
+                    .with_context(|| format!("failed to load buffer {}", buffer.index()))?,
+            };
+
+            if data.len() < buffer.length() {
+                bail!(
+                    "buffer {} is {} bytes long, but should be at least {} bytes",
+                    buffer.index(),
+                    data.len(),
+                    buffer.length()
+                );
+            }
+
+            Ok(data)
+        })
+        .collect()
+}
+
+/// Reads the data a URI in a glTF document points to. Relative paths are
+/// resolved against `base`.
+fn read_uri(base: &Path, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or_else(|| SquishError::Unsupported {
+                feature: "a data URI that isn't base64 encoded".into(),
+                image: None,
+            })?;
+        return base64::decode(encoded).context("invalid base64 in data URI");
+    }
+
+    Ok(fs_err::read(uri_path(base, uri)?)?)
+}
+
+/// Works out the path of the file a (non-data) URI points to.
+fn uri_path(base: &Path, uri: &str) -> anyhow::Result<PathBuf> {
+    let path = match uri
+        .strip_prefix("file://")
+        .or_else(|| uri.strip_prefix("file:"))
+    {
+        Some(path) => PathBuf::from(percent_decode(path)),
+        None if uri.contains(':') => {
+            return Err(SquishError::Unsupported {
+                feature: format!("the URI scheme in {uri}"),
+                image: None,
+            }
+            .into())
+        }
+        None => base.join(percent_decode(uri)),
+    };
+
+    Ok(path)
+}
+
+/// Decodes the `%XX` escapes in a URI. Invalid escapes are left as-is.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Works out the MIME type of an image from its URI, for images that don't
+/// specify one.
+fn uri_mime_type(uri: &str) -> Option<&str> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        return data.split_once(";base64,").map(|(mime_type, _)| mime_type);
+    }
+
+    let extension = Path::new(uri).extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "ktx2" => Some("image/ktx2"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command_line() {
+        let cli = Cli::try_parse_from(["squisher", "input.glb", "output.glb"]).unwrap();
+        assert!(cli.squish.is_some());
+
+        let cli = Cli::try_parse_from([
+            "squisher",
+            "--max-file-size",
+            "2MiB",
+            "input.glb",
+            "output.glb",
+        ])
+        .unwrap();
+        assert!(cli.command.is_none());
+        let args = cli.squish.unwrap();
+        assert_eq!(args.max_file_size, Some(budget::ByteSize(2 << 20)));
+
+        let cli = Cli::try_parse_from([
+            "squisher",
+            "--profile",
+            "quest2,pcvr",
+            "input.glb",
+            "output.glb",
+        ])
+        .unwrap();
+        assert_eq!(cli.squish.unwrap().profile, ["quest2", "pcvr"]);
+
+        let cli = Cli::try_parse_from(["squisher", "inspect", "input.glb"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Inspect(_))));
+        assert!(cli.squish.is_none());
+    }
+
+    #[test]
+    fn command_line_overrides_profile() {
+        let profile = config::Profile {
+            formats: HashMap::from([(TextureType::Normal, TextureFormat::Rgba8)]),
+            block_sizes: HashMap::from([(TextureType::BaseColor, "8x8".into())]),
+            quality: HashMap::from([(TextureType::Emissive, 30.0)]),
+            auto_block_size: Some(true),
+            ..Default::default()
+        };
+
+        let cli = Cli::try_parse_from(["squisher", "in.glb", "out.glb"]).unwrap();
+        let settings = texture_settings(&cli.squish.unwrap(), &profile);
+        assert_eq!(settings[&TextureType::Normal].format, TextureFormat::Rgba8);
+        assert!(settings[&TextureType::Normal].quality_target.is_none());
+        assert_eq!(settings[&TextureType::BaseColor].block_size, "8x8");
+        assert_eq!(
+            settings[&TextureType::MetallicRoughnessOcclusion].block_size,
+            "4x4"
+        );
+        let emissive = settings[&TextureType::Emissive].quality_target.unwrap();
+        assert_eq!(emissive.threshold, 30.0);
+
+        let cli = Cli::try_parse_from([
+            "squisher",
+            "--format",
+            "astc",
+            "--quality-target",
+            "emissive=45",
+            "in.glb",
+            "out.glb",
+        ])
+        .unwrap();
+        let settings = texture_settings(&cli.squish.unwrap(), &profile);
+        assert_eq!(settings[&TextureType::Normal].format, TextureFormat::Astc);
+        let emissive = settings[&TextureType::Emissive].quality_target.unwrap();
+        assert_eq!(emissive.threshold, 45.0);
+    }
+
+    #[test]
+    fn target_paths() {
+        let path = Path::new("out/model.glb");
+        assert_eq!(target_path(path, None), path);
+        assert_eq!(
+            target_path(path, Some("quest2")),
+            Path::new("out/model.quest2.glb")
+        );
+        assert_eq!(
+            target_path(Path::new("report"), Some("web")),
+            Path::new("report.web")
+        );
+    }
+
+    /// The arguments for squishing without the cache, for tests to override
+    /// the fields they care about.
+    fn test_args(input: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Args {
+        Args {
+            input: input.into(),
+            output: output.into(),
+            format: None,
+            verbose: true,
+            no_cache: true,
+            no_supercompression: false,
+            no_prune: false,
+            no_dedup: false,
+            allow_invalid: false,
+            keep_going: false,
+            progress: None,
+            depfile: None,
+            stamp: None,
+            watch: false,
+            toktx_timeout: 600,
+            report: None,
+            max_file_size: None,
+            max_gpu_memory: None,
+            max_texture_size: None,
+            auto_block_size: false,
+            quality_target: Vec::new(),
+            profile: Vec::new(),
+            config: None,
+            max_size: None,
+        }
+    }
+
+    #[test]
+    fn glb_astc() {
+        let args = Args {
+            format: Some(TextureFormat::Astc),
+            ..test_args(
+                "test_data/BoxTexturedBinary.glb",
+                "test_output/BoxTexturedBinary_astc.glb",
+            )
+        };
+
+        let verification = VerifyArgs {
+            path: "test_output/BoxTexturedBinary_astc.glb",
+            format: ktx2::Format::ASTC_6x6_SRGB_BLOCK,
+            mip_level_count: 9,
+        };
+
+        fs_err::create_dir_all("test_output").unwrap();
+        squish(args).unwrap();
+        verify(verification);
+
+        let output = open("test_output/BoxTexturedBinary_astc.glb".as_ref()).unwrap();
+        let generator = output.document.into_json().asset.generator.unwrap();
+        assert!(generator.starts_with("squisher "), "{generator}");
+        let toktx = toktx::Toktx::find(None, Duration::from_secs(600)).unwrap();
+        let expected = format!(" with toktx {}", toktx.version());
+        assert!(generator.ends_with(&expected), "{generator}");
+    }
+
+    #[test]
+    fn glb_rgba8() {
+        let args = Args {
+            format: Some(TextureFormat::Rgba8),
+            ..test_args(
+                "test_data/BoxTexturedBinary.glb",
+                "test_output/BoxTexturedBinary_raw.glb",
+            )
+        };
+
+        let verification = VerifyArgs {
+            path: "test_output/BoxTexturedBinary_raw.glb",
+            format: ktx2::Format::R8G8B8A8_SRGB,
+            mip_level_count: 9,
+        };
+
+        fs_err::create_dir_all("test_output").unwrap();
+        squish(args).unwrap();
+        verify(verification);
+    }
+
+    #[test]
+    fn auto_block_size_supercompressed() {
+        let args = Args {
+            auto_block_size: true,
+            ..test_args(
+                "test_data/BoxTexturedBinary.glb",
+                "test_output/BoxTexturedBinary_auto.glb",
+            )
+        };
+
+        fs_err::create_dir_all("test_output").unwrap();
+        squish(args).unwrap();
+
+        // The block size is picked from supercompressed attempts, which are
+        // used as they are.
+        let output = open("test_output/BoxTexturedBinary_auto.glb".as_ref()).unwrap();
+        for image in output.document.images() {
+            let (bytes, _) = output.image_data(&image).unwrap();
+            let header = ktx2::Reader::new(&*bytes).unwrap().header();
+            assert_eq!(
+                header.supercompression_scheme,
+                Some(ktx2::SupercompressionScheme::Zstandard)
+            );
+        }
+    }
+
+    #[test]
+    fn multiple_profiles() {
+        fs_err::create_dir_all("test_output").unwrap();
+        let config = "test_output/multiple_profiles.toml";
+        fs_err::write(
+            config,
+            "[profiles.small]\nmax-size = 64\n\n[profiles.raw.formats]\nbase-color = \"rgba8\"\n",
+        )
+        .unwrap();
+
+        let args = Args {
+            no_supercompression: true,
+            profile: vec!["small".into(), "raw".into()],
+            config: Some(config.into()),
+            ..test_args(
+                "test_data/BoxTexturedBinary.glb",
+                "test_output/BoxTexturedBinary_targets.glb",
+            )
+        };
+        squish(args).unwrap();
+
+        verify(VerifyArgs {
+            path: "test_output/BoxTexturedBinary_targets.small.glb",
+            format: ktx2::Format::ASTC_6x6_SRGB_BLOCK,
+            mip_level_count: 7,
+        });
+        verify(VerifyArgs {
+            path: "test_output/BoxTexturedBinary_targets.raw.glb",
+            format: ktx2::Format::R8G8B8A8_SRGB,
+            mip_level_count: 9,
+        });
+    }
+
+    #[test]
+    fn ignores_unrelated_config() {
+        let dir = Path::new("test_output/unrelated_config");
+        let _ = fs_err::remove_dir_all(dir);
+        fs_err::create_dir_all(dir).unwrap();
+        fs_err::write(dir.join(config::FILE_NAME), "[something-else\n").unwrap();
+        let input = dir.join("box.glb");
+        fs_err::copy("test_data/BoxTexturedBinary.glb", &input).unwrap();
+
+        let args = |profile: Vec<String>| Args {
+            format: Some(TextureFormat::Rgba8),
+            profile,
+            ..test_args(input.clone(), dir.join("box_squished.glb"))
+        };
+
+        // Nothing needs the config, so it's only warned about.
+        squish(args(Vec::new())).unwrap();
+        assert!(dir.join("box_squished.glb").exists());
+
+        // But a profile can't come from a broken config.
+        let error = squish(args(vec!["quest2".into()])).unwrap_err();
+        assert!(format!("{error:#}").contains("invalid config"), "{error:#}");
+    }
+
+    #[test]
+    fn sidecar_skips_image() {
+        let dir = Path::new("test_output/sidecar_skips_image");
+        fs_err::create_dir_all(dir).unwrap();
+        let input = dir.join("box.glb");
+        fs_err::copy("test_data/BoxTexturedBinary.glb", &input).unwrap();
+
+        let original = open(&input).unwrap();
+        let image = original.document.images().next().unwrap();
+        let (png, _) = original.image_data(&image).unwrap();
+        fs_err::write(
+            dir.join("box.squisher.toml"),
+            format!("[images.{}]\nskip = true\n", overrides::content_hash(&png)),
+        )
+        .unwrap();
+
+        let output = dir.join("box_squished.glb");
+        squish(test_args(input, output.clone())).unwrap();
+
+        let output = open(&output).unwrap();
+        let image = output.document.images().next().unwrap();
+        let (bytes, mime_type) = output.image_data(&image).unwrap();
+        assert_eq!(mime_type, Some("image/png"));
+        assert_eq!(bytes, png);
+    }
+
+    #[test]
+    fn missing_files() {
+        let dir = Path::new("test_output/missing_files");
+        let _ = fs_err::remove_dir_all(dir);
+        fs_err::create_dir_all(dir).unwrap();
+
+        // A buffer that isn't there means the input is broken.
+        let input = dir.join("BoxTexturedMultiBuffer.gltf");
+        fs_err::copy("test_data/BoxTexturedMultiBuffer.gltf", &input).unwrap();
+        let Err(err) = open(&input) else {
+            panic!("opened a glTF without its buffer");
+        };
+        assert_eq!(SquishError::find(&err).unwrap().exit_code(), 3);
+
+        // An image that isn't there can't be compressed.
+        let json = fs_err::read_to_string("test_data/BoxTextured.gltf").unwrap();
+        let mut root: gltf::json::Root = gltf::json::deserialize::from_str(&json).unwrap();
+        root.images[0].uri = Some("missing.png".into());
+        let input = dir.join("box.gltf");
+        fs_err::write(&input, gltf::json::serialize::to_string(&root).unwrap()).unwrap();
+        let args = test_args(input, dir.join("box_squished.glb"));
+        let err = squish(args).unwrap_err();
+        let kind = SquishError::find(&err).unwrap_or_else(|| panic!("{err:?}"));
+        assert_eq!(kind.exit_code(), 6);
+    }
+
+    #[test]
+    fn keep_going_leaves_broken_images() {
+        let dir = Path::new("test_output/keep_going");
+        fs_err::create_dir_all(dir).unwrap();
+        let json = fs_err::read_to_string("test_data/BoxTextured.gltf").unwrap();
+        let mut root: gltf::json::Root = gltf::json::deserialize::from_str(&json).unwrap();
+        root.images[0].uri = Some("broken.png".into());
+        let input = dir.join("box.gltf");
+        fs_err::write(
+            &input,
+            gltf::json::serialize::to_string_pretty(&root).unwrap(),
+        )
+        .unwrap();
+        fs_err::write(dir.join("broken.png"), b"not a png").unwrap();
+
+        let output = dir.join("box_squished.glb");
+        let _ = fs_err::remove_file(&output);
+        let report = dir.join("report.json");
+        let args = |keep_going| Args {
+            allow_invalid: true,
+            keep_going,
+            report: Some(report.clone()),
+            ..test_args(input.clone(), output.clone())
+        };
+
+        let err = squish(args(false)).unwrap_err();
+        assert_eq!(SquishError::find(&err).unwrap().exit_code(), 6);
+        assert!(!output.exists());
+
+        let err = squish(args(true)).unwrap_err();
+        assert!(matches!(
+            SquishError::find(&err),
+            Some(SquishError::Partial { count: 1 })
+        ));
+
+        let output = open(&output).unwrap();
+        let image = output.document.images().next().unwrap();
+        let (bytes, mime_type) = output.image_data(&image).unwrap();
+        assert_eq!(mime_type, Some("image/png"));
+        assert_eq!(&*bytes, b"not a png");
+
+        let report: gltf::json::Value =
+            gltf::json::deserialize::from_slice(&fs_err::read(&report).unwrap()).unwrap();
+        assert_eq!(report["failures"][0]["index"], 0);
+        assert_eq!(report["failures"][0]["texture_type"], "BaseColor");
+    }
+
+    #[test]
+    fn stamp_skips_unchanged() {
+        let dir = Path::new("test_output/stamp");
+        let _ = fs_err::remove_dir_all(dir);
+        fs_err::create_dir_all(dir).unwrap();
+        for file in ["BoxTexturedMultiBuffer.gltf", "BoxTexturedMultiBuffer.bin"] {
+            fs_err::copy(Path::new("test_data").join(file), dir.join(file)).unwrap();
+        }
+
+        let output = dir.join("box.glb");
+        let args = |format| Args {
+            format,
+            depfile: Some(dir.join("box.d")),
+            stamp: Some(dir.join("box.stamp")),
+            report: Some(dir.join("box.json")),
+            ..test_args(dir.join("BoxTexturedMultiBuffer.gltf"), output.clone())
+        };
+
+        squish(args(None)).unwrap();
+        let depfile = fs_err::read_to_string(dir.join("box.d")).unwrap();
+        assert!(
+            depfile.starts_with("test_output/stamp/box.glb:"),
+            "{depfile}"
+        );
+        assert!(depfile.contains("BoxTexturedMultiBuffer.bin"), "{depfile}");
+
+        // Nothing has changed, so the output is left alone.
+        fs_err::write(&output, "left alone").unwrap();
+        squish(args(None)).unwrap();
+        assert_eq!(fs_err::read(&output).unwrap(), b"left alone");
+
+        // Unless the report has gone missing.
+        fs_err::remove_file(dir.join("box.json")).unwrap();
+        squish(args(None)).unwrap();
+        assert_ne!(fs_err::read(&output).unwrap(), b"left alone");
+        assert!(dir.join("box.json").exists());
+
+        // But changing a setting, or a file the input refers to, squishes it again.
+        squish(args(Some(TextureFormat::Rgba8))).unwrap();
+        assert_ne!(fs_err::read(&output).unwrap(), b"left alone");
+
+        fs_err::write(&output, "left alone").unwrap();
+        let bin = dir.join("BoxTexturedMultiBuffer.bin");
+        let mut buffer = fs_err::read(&bin).unwrap();
+        buffer.push(0);
+        fs_err::write(&bin, buffer).unwrap();
+        squish(args(Some(TextureFormat::Rgba8))).unwrap();
+        assert_ne!(fs_err::read(&output).unwrap(), b"left alone");
+    }
+
+    #[test]
+    fn reproducible_output() {
+        let dir = Path::new("test_output/reproducible");
+        let _ = fs_err::remove_dir_all(dir);
+        fs_err::create_dir_all(dir).unwrap();
+        let config = dir.join("squisher.toml");
+        fs_err::write(
+            &config,
+            "[cache]\ndir = \"cache\"\n\n[profiles.small]\nmax-size = 128\n",
+        )
+        .unwrap();
+
+        let squish_to = |name: &str, no_cache| {
+            let output = dir.join(name);
+            let args = Args {
+                no_cache,
+                profile: vec!["small".into()],
+                config: Some(config.clone()),
+                ..test_args("test_data/BoxTexturedBinary.glb", output.clone())
+            };
+            squish(args).unwrap();
+            fs_err::read(output).unwrap()
+        };
+
+        // Without the cache, with it cold and with it warm.
+        let uncached = squish_to("uncached.glb", true);
+        assert_eq!(squish_to("cold.glb", false), uncached);
+        assert_eq!(squish_to("warm.glb", false), uncached);
+
+        let writer = b"KTXwriter";
+        assert!(!uncached.windows(writer.len()).any(|bytes| bytes == writer));
+    }
+
+    #[test]
+    fn already_squished() {
+        let first_args = Args {
+            format: Some(TextureFormat::Rgba8),
+            ..test_args(
+                "test_data/BoxTexturedBinary.glb",
+                "test_output/already_squished_1.glb",
+            )
+        };
+
+        squish(first_args).unwrap();
+
+        let second_args = Args {
+            format: Some(TextureFormat::Rgba8),
+            ..test_args(
+                "test_output/already_squished_1.glb",
+                "test_output/already_squished_2.glb",
+            )
+        };
+
+        squish(second_args).unwrap();
+
+        verify(VerifyArgs {
+            path: "test_output/already_squished_2.glb",
+            format: ktx2::Format::R8G8B8A8_SRGB,
+            mip_level_count: 9,
+        });
+    }
+
+    #[test]
+    fn gltf_multiple_buffers() {
+        let args = Args {
+            format: Some(TextureFormat::Rgba8),
+            ..test_args(
+                "test_data/BoxTexturedMultiBuffer.gltf",
+                "test_output/BoxTexturedMultiBuffer.glb",
+            )
+        };
+
+        let verification = VerifyArgs {
+            path: "test_output/BoxTexturedMultiBuffer.glb",
+            format: ktx2::Format::R8G8B8A8_SRGB,
+            mip_level_count: 9,
+        };
+
+        fs_err::create_dir_all("test_output").unwrap();
+        let outputs = squish_targets(&args, &Session::default()).unwrap();
+        verify(verification);
+
+        // The external buffer counts towards the size of the input.
+        let input_len = ["gltf", "bin"]
+            .iter()
+            .map(|ext| {
+                let path = Path::new("test_data/BoxTexturedMultiBuffer").with_extension(ext);
+                fs_err::metadata(path).unwrap().len() as usize
+            })
+            .sum::<usize>();
+        assert_eq!(outputs[0].report.input_file_bytes, input_len);
+    }
+
+    #[test]
+    fn open_multiple_buffers() {
+        let input = open("test_data/BoxTexturedMultiBuffer.gltf".as_ref()).unwrap();
+        assert_eq!(input.buffers.len(), 2);
+
+        // The geometry lives in an external file, everything else is in a data URI.
+        let expected = fs_err::read("test_data/BoxTexturedMultiBuffer.bin").unwrap();
+        assert_eq!(input.buffers[0], expected);
+
+        let image = input.document.images().next().unwrap();
+        match image.source() {
+            gltf::image::Source::View { view, mime_type } => {
+                assert_eq!(view.buffer().index(), 1);
+                assert_eq!(mime_type, "image/png");
+
+                let expected = fs_err::read("test_data/BoxTexturedBinary_img0.png").unwrap();
+                assert_eq!(input.view_data(&view), expected);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    struct VerifyArgs {
+        path: &'static str,
+        format: ktx2::Format,
+        mip_level_count: u32,
+    }
+
+    fn verify(args: VerifyArgs) {
+        let path: &Path = args.path.as_ref();
+        assert!(path.exists());
+
+        let input = open(path).unwrap();
+        for image in input.document.images() {
+            match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    // Get the image, then make sure it was compressed correctly.
+                    let bytes = input.view_data(&view);
+                    let reader = ktx2::Reader::new(bytes).unwrap();
+                    let header = reader.header();
+
+                    assert_eq!(header.format, Some(args.format));
+                    assert_eq!(header.level_count, args.mip_level_count);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Settings for individual textures, for the odd texture that needs to be
+//! treated differently to the rest of its type.
+//!
+//! They can be set in the `extras` of a material, texture or image:
+//!
+//! ```json
+//! "extras": { "squisher": { "max-size": 2048, "block-size": "4x4" } }
+//! ```
+//!
+//! or in a `model.squisher.toml` next to `model.glb`, keyed by the image's
+//! name, URI or content hash:
+//!
+//! ```toml
+//! [images."Wood Floor"]
+//! skip = true
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hasher,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{quality, Input, TextureFormat, TextureSettings, TextureType, BLOCK_SIZES};
+
+/// The settings for one texture. Anything that isn't set comes from the
+/// settings for its texture type.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Override {
+    /// Leave the image exactly as it is.
+    pub(crate) skip: Option<bool>,
+    pub(crate) format: Option<TextureFormat>,
+    /// The ASTC block size, eg. "4x4".
+    pub(crate) block_size: Option<String>,
+    /// The largest width or height the texture can have before it's shrunk.
+    pub(crate) max_size: Option<u32>,
+    /// A quality target to pick the block size automatically, in the same
+    /// units as `--quality-target`.
+    pub(crate) quality: Option<f64>,
+}
+
+/// The overrides in a `model.squisher.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Sidecar {
+    /// Overrides by image name, URI or content hash.
+    #[serde(default)]
+    images: HashMap<String, Override>,
+}
+
+/// The `extras` of a glTF object, where we only care about our own key.
+#[derive(Deserialize)]
+struct Extras {
+    squisher: Option<Override>,
+}
+
+impl Override {
+    fn check(&self) -> anyhow::Result<()> {
+        if let Some(block_size) = &self.block_size {
+            if !BLOCK_SIZES.contains(&block_size.as_str()) {
+                bail!(
+                    "unknown block size '{block_size}', expected one of {}",
+                    BLOCK_SIZES.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Combines two overrides, with anything set in `other` winning.
+    fn merge(&mut self, other: Override) {
+        self.skip = other.skip.or(self.skip);
+        self.format = other.format.or(self.format);
+        self.block_size = other.block_size.or(self.block_size.take());
+        self.max_size = other.max_size.or(self.max_size);
+        self.quality = other.quality.or(self.quality);
+    }
+
+    /// Applies the override to the settings for the texture's type.
+    pub(crate) fn apply(
+        &self,
+        mut settings: TextureSettings,
+        texture_type: TextureType,
+    ) -> TextureSettings {
+        if let Some(format) = self.format {
+            settings.format = format;
+        }
+
+        // A block size for this texture wins over picking one automatically
+        // for its type, but not over a quality target for this texture.
+        let block_size = self
+            .block_size
+            .as_deref()
+            .and_then(|size| BLOCK_SIZES.iter().find(|s| **s == size));
+        if let Some(block_size) = block_size {
+            settings.block_size = block_size;
+            settings.quality_target = None;
+        }
+        if let Some(threshold) = self.quality {
+            settings.quality_target = Some(quality::QualityTarget {
+                texture_type,
+                threshold,
+            });
+        }
+
+        if settings.format != TextureFormat::Astc {
+            settings.quality_target = None;
+        }
+        settings
+    }
+}
+
+impl Sidecar {
+    /// The path of the sidecar for an input, eg. `model.squisher.toml` for
+    /// `model.glb`.
+    pub fn path(input: &Path) -> PathBuf {
+        input.with_extension(crate::config::FILE_NAME)
+    }
+
+    /// Loads the sidecar next to an input, if there is one.
+    pub(crate) fn load(input: &Path) -> anyhow::Result<Self> {
+        let path = Self::path(input);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        log::info!("Using texture overrides from {}", path.display());
+        let text = fs_err::read_to_string(&path)?;
+        let sidecar: Sidecar = toml::from_str(&text)
+            .with_context(|| format!("invalid overrides {}", path.display()))?;
+        for (key, image) in &sidecar.images {
+            image
+                .check()
+                .with_context(|| format!("invalid overrides for image '{key}'"))?;
+        }
+
+        Ok(sidecar)
+    }
+
+    /// Works out the overrides for a texture used by a material. Those in
+    /// the sidecar win over the extras of the image, which win over the
+    /// texture's, which win over the material's.
+    pub(crate) fn overrides(
+        &self,
+        material: &gltf::Material,
+        texture: &gltf::Texture,
+        bytes: &[u8],
+    ) -> anyhow::Result<Override> {
+        let image = texture.source();
+        let mut result = Override::default();
+
+        let extras = [
+            ("material", material.index(), material.extras()),
+            ("texture", Some(texture.index()), texture.extras()),
+            ("image", Some(image.index()), image.extras()),
+        ];
+        for (kind, index, extras) in extras {
+            if let Some(extras) = from_extras(extras).with_context(|| {
+                let index = index.map_or_else(|| "default".to_string(), |i| i.to_string());
+                format!("invalid squisher extras on {kind} {index}")
+            })? {
+                result.merge(extras);
+            }
+        }
+
+        let uri = match image.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        };
+        let hash = content_hash(bytes);
+        let keys = [image.name(), uri, Some(hash.as_str())];
+        for key in keys.into_iter().flatten() {
+            if let Some(image) = self.images.get(key) {
+                result.merge(image.clone());
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// The keys that don't match any image in the input, which are most
+    /// likely typos or hashes of images that have since changed.
+    pub(crate) fn unused_keys(&self, input: &Input) -> Vec<&str> {
+        let mut keys = HashSet::new();
+        for image in input.document.images() {
+            keys.extend(image.name().map(str::to_string));
+            if let gltf::image::Source::Uri { uri, .. } = image.source() {
+                keys.insert(uri.to_string());
+            }
+            if let Ok((bytes, _)) = input.image_data(&image) {
+                keys.insert(content_hash(&bytes));
+            }
+        }
+
+        let mut unused: Vec<_> = self
+            .images
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !keys.contains(*key))
+            .collect();
+        unused.sort_unstable();
+        unused
+    }
+}
+
+fn from_extras(extras: &gltf::json::Extras) -> anyhow::Result<Option<Override>> {
+    let Some(extras) = extras else {
+        return Ok(None);
+    };
+
+    // Other tools put all sorts in extras, including things that aren't
+    // objects, so only look closer if it has our key.
+    let value: gltf::json::Value = gltf::json::deserialize::from_str(extras.get())?;
+    if value.get("squisher").is_none() {
+        return Ok(None);
+    }
+
+    let extras: Extras = gltf::json::deserialize::from_value(value)?;
+    if let Some(extras) = &extras.squisher {
+        extras.check()?;
+    }
+    Ok(extras.squisher)
+}
+
+/// The hash that identifies an image in a sidecar, as 16 hexadecimal digits.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hasher = seahash::SeaHasher::new();
+    hasher.write(bytes);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extras_and_sidecar() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "images": [{ "uri": "wood.png", "extras": { "squisher": { "max-size": 2048 } } }],
+            "textures": [{ "source": 0, "extras": { "author": "someone" } }],
+            "materials": [{
+                "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } },
+                "extras": { "squisher": { "max-size": 1024, "block-size": "4x4" } }
+            }]
+        }"#;
+        let document = gltf::Gltf::from_slice(json.as_bytes()).unwrap().document;
+        let material = document.materials().next().unwrap();
+        let texture = document.textures().next().unwrap();
+
+        let overrides = Sidecar::default()
+            .overrides(&material, &texture, b"png")
+            .unwrap();
+        assert_eq!(overrides.max_size, Some(2048));
+        assert_eq!(overrides.block_size.as_deref(), Some("4x4"));
+        assert_eq!(overrides.skip, None);
+
+        let sidecar: Sidecar = toml::from_str(&format!(
+            "[images.\"wood.png\"]\nblock-size = \"8x8\"\n\n[images.{}]\nskip = true\n",
+            content_hash(b"png")
+        ))
+        .unwrap();
+        let overrides = sidecar.overrides(&material, &texture, b"png").unwrap();
+        assert_eq!(overrides.max_size, Some(2048));
+        assert_eq!(overrides.block_size.as_deref(), Some("8x8"));
+        assert_eq!(overrides.skip, Some(true));
+    }
+
+    #[test]
+    fn unused_keys() {
+        let input = crate::open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let image = input.document.images().next().unwrap();
+        let hash = content_hash(&input.image_data(&image).unwrap().0);
+
+        let sidecar: Sidecar = toml::from_str(&format!(
+            "[images.{hash}]\nskip = true\n\n[images.Wod]\nskip = true\n"
+        ))
+        .unwrap();
+        assert_eq!(sidecar.unused_keys(&input), ["Wod"]);
+    }
+
+    #[test]
+    fn apply_to_settings() {
+        let settings = TextureSettings {
+            format: TextureFormat::Astc,
+            block_size: "6x6",
+            quality_target: Some(quality::QualityTarget::default_for(TextureType::BaseColor)),
+        };
+
+        let block_size = Override {
+            block_size: Some("4x4".into()),
+            ..Default::default()
+        };
+        let applied = block_size.apply(settings, TextureType::BaseColor);
+        assert_eq!(applied.block_size, "4x4");
+        assert!(applied.quality_target.is_none());
+
+        let rgba8 = Override {
+            format: Some(TextureFormat::Rgba8),
+            ..Default::default()
+        };
+        let applied = rgba8.apply(settings, TextureType::BaseColor);
+        assert_eq!(applied.format, TextureFormat::Rgba8);
+        assert!(applied.quality_target.is_none());
+
+        let bad = Override {
+            block_size: Some("7x7".into()),
+            ..Default::default()
+        };
+        assert!(bad.check().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! `--progress json`, which reports what squisher is doing as one JSON event
+//! per line on stdout, for tools that wrap squisher and want to show progress
+//! without scraping the log.
+//!
+//! ```json
+//! {"event":"texture_finished","image":0,"name":"Wood","texture_type":"BaseColor","bytes":43690,"seconds":1.52}
+//! ```
+
+use std::{io::Write, path::Path, str::FromStr};
+
+use anyhow::bail;
+use serde::Serialize;
+
+/// How to report progress, other than the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown progress format '{s}', expected 'json'"),
+        }
+    }
+}
+
+/// Something that happened while squishing. Every queued texture is
+/// followed by exactly one of `texture_skipped`, `texture_cached`,
+/// `texture_finished` or `texture_failed`, unless the squish fails.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    FileStarted {
+        input: &'a Path,
+        output: &'a Path,
+        /// The profile being squished for, if there's more than one.
+        profile: Option<&'a str>,
+    },
+    TextureQueued {
+        #[serde(flatten)]
+        texture: Texture<'a>,
+    },
+    TextureStarted {
+        #[serde(flatten)]
+        texture: Texture<'a>,
+    },
+    /// The image was left as it is, because it's already KTX2 or its
+    /// overrides asked.
+    TextureSkipped {
+        #[serde(flatten)]
+        texture: Texture<'a>,
+    },
+    TextureCached {
+        #[serde(flatten)]
+        texture: Texture<'a>,
+        bytes: usize,
+        seconds: f64,
+    },
+    TextureFinished {
+        #[serde(flatten)]
+        texture: Texture<'a>,
+        bytes: usize,
+        seconds: f64,
+    },
+    /// The texture couldn't be compressed, and `--keep-going` left it as it
+    /// was.
+    TextureFailed {
+        #[serde(flatten)]
+        texture: Texture<'a>,
+        error: String,
+    },
+    Warning {
+        message: String,
+    },
+    /// Something went wrong. When watching, squisher carries on afterwards.
+    Error {
+        message: String,
+    },
+    FileFinished {
+        output: &'a Path,
+        input_bytes: usize,
+        output_bytes: usize,
+        seconds: f64,
+    },
+}
+
+/// Which texture an event is about.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Texture<'a> {
+    /// The index of the image.
+    pub image: usize,
+    pub name: Option<&'a str>,
+    pub texture_type: &'a str,
+}
+
+/// Where progress events go, if anywhere.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Progress {
+    json: bool,
+}
+
+impl Progress {
+    pub fn new(format: Option<ProgressFormat>) -> Self {
+        Progress {
+            json: format == Some(ProgressFormat::Json),
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        if !self.json {
+            return;
+        }
+
+        // Progress is best effort: a reader that's gone away shouldn't stop
+        // the squish.
+        if let Ok(line) = gltf::json::serialize::to_string(&event) {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{line}");
+            let _ = stdout.flush();
+        }
+    }
+}
+
+/// Sends logging to stderr as usual, but also reports warnings and errors as
+/// events, so wrappers see them without reading the log.
+struct Logger(env_logger::Logger);
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.0.matches(record) {
+            let message = record.args().to_string();
+            let event = match record.level() {
+                log::Level::Error => Some(Event::Error { message }),
+                log::Level::Warn => Some(Event::Warning { message }),
+                _ => None,
+            };
+            if let Some(event) = event {
+                Progress { json: true }.emit(event);
+            }
+        }
+        self.0.log(record);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Like [`crate::configure_logging`], but also reports warnings and errors as
+/// progress events.
+pub fn configure_logging(verbose: bool) {
+    let logger = crate::log_builder(verbose).build();
+    let max_level = logger.filter();
+    if log::set_boxed_logger(Box::new(Logger(logger))).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_events() {
+        let texture = Texture {
+            image: 2,
+            name: Some("Wood"),
+            texture_type: "BaseColor",
+        };
+        let event = Event::TextureCached {
+            texture,
+            bytes: 1024,
+            seconds: 0.5,
+        };
+        assert_eq!(
+            gltf::json::serialize::to_string(&event).unwrap(),
+            r#"{"event":"texture_cached","image":2,"name":"Wood","texture_type":"BaseColor","bytes":1024,"seconds":0.5}"#
+        );
+
+        assert_eq!(
+            "json".parse::<ProgressFormat>().unwrap(),
+            ProgressFormat::Json
+        );
+        assert!("xml".parse::<ProgressFormat>().is_err());
+    }
+}
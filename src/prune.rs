@@ -0,0 +1,280 @@
+//! Removal of resources that nothing in a document uses.
+//!
+//! Exported files from DCC tools routinely carry materials and textures that
+//! nothing uses. There's no point compressing and shipping them.
+
+use gltf::json::Root;
+
+use crate::remap::{IndexMap, Remap};
+
+/// Tracks which elements of each of the prunable arrays are in use.
+struct Reachable {
+    accessors: Vec<bool>,
+    buffer_views: Vec<bool>,
+    images: Vec<bool>,
+    materials: Vec<bool>,
+    meshes: Vec<bool>,
+    samplers: Vec<bool>,
+    textures: Vec<bool>,
+}
+
+/// Removes every image, texture, sampler, material, mesh, accessor and buffer
+/// view that isn't used by a node, skin or animation, and compacts the
+/// indices of what's left.
+///
+/// Nodes, skins and animations are always kept, as are the resources they
+/// depend on, even for nodes outside every scene, like joints or nodes kept
+/// for instancing later. Documents without any scenes are left untouched, as
+/// they may be used as libraries of resources.
+pub fn prune(root: &mut Root) {
+    if root.scenes.is_empty() {
+        log::debug!("Document has no scenes, skipping pruning");
+        return;
+    }
+
+    let mut reachable = Reachable {
+        accessors: vec![false; root.accessors.len()],
+        buffer_views: vec![false; root.buffer_views.len()],
+        images: vec![false; root.images.len()],
+        materials: vec![false; root.materials.len()],
+        meshes: vec![false; root.meshes.len()],
+        samplers: vec![false; root.samplers.len()],
+        textures: vec![false; root.textures.len()],
+    };
+
+    for node in &root.nodes {
+        if let Some(mesh) = node.mesh {
+            reachable.mark_mesh(root, mesh.value());
+        }
+    }
+
+    for skin in &root.skins {
+        if let Some(accessor) = skin.inverse_bind_matrices {
+            reachable.mark_accessor(root, accessor.value());
+        }
+    }
+
+    for animation in &root.animations {
+        for sampler in &animation.samplers {
+            reachable.mark_accessor(root, sampler.input.value());
+            reachable.mark_accessor(root, sampler.output.value());
+        }
+    }
+
+    let remap = Remap {
+        accessors: IndexMap::retain(&reachable.accessors),
+        buffer_views: IndexMap::retain(&reachable.buffer_views),
+        images: IndexMap::retain(&reachable.images),
+        materials: IndexMap::retain(&reachable.materials),
+        meshes: IndexMap::retain(&reachable.meshes),
+        samplers: IndexMap::retain(&reachable.samplers),
+        textures: IndexMap::retain(&reachable.textures),
+    };
+
+    for (kind, used) in [
+        ("images", &reachable.images),
+        ("textures", &reachable.textures),
+        ("samplers", &reachable.samplers),
+        ("materials", &reachable.materials),
+        ("meshes", &reachable.meshes),
+        ("accessors", &reachable.accessors),
+        ("buffer views", &reachable.buffer_views),
+    ] {
+        let removed = used.iter().filter(|used| !**used).count();
+        if removed > 0 {
+            log::info!("Removing {removed} unused {kind}");
+        }
+    }
+
+    remap.apply(root);
+}
+
+impl Reachable {
+    fn mark_mesh(&mut self, root: &Root, index: usize) {
+        if std::mem::replace(&mut self.meshes[index], true) {
+            return;
+        }
+
+        for primitive in &root.meshes[index].primitives {
+            for accessor in primitive.attributes.values() {
+                self.mark_accessor(root, accessor.value());
+            }
+            if let Some(accessor) = primitive.indices {
+                self.mark_accessor(root, accessor.value());
+            }
+            if let Some(material) = primitive.material {
+                self.mark_material(root, material.value());
+            }
+
+            for target in primitive.targets.iter().flatten() {
+                for accessor in [target.positions, target.normals, target.tangents]
+                    .into_iter()
+                    .flatten()
+                {
+                    self.mark_accessor(root, accessor.value());
+                }
+            }
+        }
+    }
+
+    fn mark_material(&mut self, root: &Root, index: usize) {
+        if std::mem::replace(&mut self.materials[index], true) {
+            return;
+        }
+
+        let material = &root.materials[index];
+        let pbr = &material.pbr_metallic_roughness;
+        let textures = [
+            pbr.base_color_texture.as_ref().map(|t| t.index),
+            pbr.metallic_roughness_texture.as_ref().map(|t| t.index),
+            material.normal_texture.as_ref().map(|t| t.index),
+            material.occlusion_texture.as_ref().map(|t| t.index),
+            material.emissive_texture.as_ref().map(|t| t.index),
+        ];
+
+        for texture in textures.into_iter().flatten() {
+            self.mark_texture(root, texture.value());
+        }
+    }
+
+    fn mark_texture(&mut self, root: &Root, index: usize) {
+        if std::mem::replace(&mut self.textures[index], true) {
+            return;
+        }
+
+        let texture = &root.textures[index];
+        if let Some(sampler) = texture.sampler {
+            self.samplers[sampler.value()] = true;
+        }
+
+        let image = texture.source.value();
+        self.images[image] = true;
+        if let Some(view) = root.images[image].buffer_view {
+            self.buffer_views[view.value()] = true;
+        }
+    }
+
+    fn mark_accessor(&mut self, root: &Root, index: usize) {
+        if std::mem::replace(&mut self.accessors[index], true) {
+            return;
+        }
+
+        let accessor = &root.accessors[index];
+        if let Some(view) = accessor.buffer_view {
+            self.buffer_views[view.value()] = true;
+        }
+        if let Some(sparse) = &accessor.sparse {
+            self.buffer_views[sparse.indices.buffer_view.value()] = true;
+            self.buffer_views[sparse.values.buffer_view.value()] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::json::Index;
+
+    fn box_textured() -> Root {
+        let file = fs_err::File::open("test_data/BoxTextured.gltf").unwrap();
+        Root::from_reader(file).unwrap()
+    }
+
+    #[test]
+    fn keeps_used_resources() {
+        let mut root = box_textured();
+        prune(&mut root);
+
+        assert_eq!(root.images.len(), 1);
+        assert_eq!(root.textures.len(), 1);
+        assert_eq!(root.samplers.len(), 1);
+        assert_eq!(root.materials.len(), 1);
+        assert_eq!(root.meshes.len(), 1);
+        assert_eq!(root.accessors.len(), 4);
+        assert_eq!(root.buffer_views.len(), 3);
+    }
+
+    #[test]
+    fn removes_unused_resources() {
+        let mut root = box_textured();
+
+        // Add a material that nothing uses, sitting in front of the real one,
+        // with its own texture, image and buffer view.
+        root.buffer_views.insert(0, root.buffer_views[0].clone());
+        for accessor in &mut root.accessors {
+            let view = accessor.buffer_view.unwrap();
+            accessor.buffer_view = Some(Index::new(view.value() as u32 + 1));
+        }
+
+        let mut image = root.images[0].clone();
+        image.uri = None;
+        image.buffer_view = Some(Index::new(0));
+        root.images.push(image);
+
+        let mut texture = root.textures[0].clone();
+        texture.source = Index::new(1);
+        root.textures.push(texture);
+
+        let mut material = root.materials[0].clone();
+        material.name = Some("Unused".into());
+        material.pbr_metallic_roughness.base_color_texture = Some(gltf::json::texture::Info {
+            index: Index::new(1),
+            tex_coord: 0,
+            extensions: None,
+            extras: Default::default(),
+        });
+        root.materials.insert(0, material);
+        root.meshes[0].primitives[0].material = Some(Index::new(1));
+
+        // And a mesh that no node uses.
+        root.meshes.push(root.meshes[0].clone());
+
+        prune(&mut root);
+
+        assert_eq!(root.images.len(), 1);
+        assert_eq!(root.textures.len(), 1);
+        assert_eq!(root.materials.len(), 1);
+        assert_eq!(root.meshes.len(), 1);
+        assert_eq!(root.buffer_views.len(), 3);
+
+        // Everything that's left should point at the right things.
+        assert_eq!(root.materials[0].name.as_deref(), Some("Texture"));
+        assert_eq!(root.meshes[0].primitives[0].material.unwrap().value(), 0);
+        assert_eq!(root.accessors[0].buffer_view.unwrap().value(), 0);
+        assert_eq!(root.accessors[0].count, 36);
+        assert_eq!(root.buffer_views[0].byte_length, 72);
+    }
+
+    #[test]
+    fn keeps_meshes_of_nodes_outside_scenes() {
+        let mut root = box_textured();
+
+        // A node that's in no scene, with its own mesh.
+        let mut mesh = root.meshes[0].clone();
+        mesh.name = Some("Spare".into());
+        root.meshes.push(mesh);
+        let mut node = root.nodes[1].clone();
+        node.mesh = Some(Index::new(1));
+        root.nodes.push(node);
+
+        prune(&mut root);
+
+        assert_eq!(root.nodes.len(), 3);
+        assert_eq!(root.meshes.len(), 2);
+        assert_eq!(root.nodes[2].mesh.unwrap().value(), 1);
+        assert_eq!(root.meshes[1].name.as_deref(), Some("Spare"));
+    }
+
+    #[test]
+    fn leaves_documents_without_scenes() {
+        let mut root = box_textured();
+        root.scenes.clear();
+        root.scene = None;
+        root.nodes.clear();
+
+        prune(&mut root);
+
+        assert_eq!(root.meshes.len(), 1);
+        assert_eq!(root.images.len(), 1);
+    }
+}
@@ -0,0 +1,443 @@
+//! Measuring how closely a compressed texture matches the image it came from.
+
+use std::{io::Read, str::FromStr};
+
+use anyhow::{bail, ensure, Context};
+use image::RgbaImage;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{budget::block_layout, TextureType};
+
+/// Colour textures also need at least this SSIM, since PSNR alone lets
+/// blocky, smeared out detail through on otherwise flat images.
+const MIN_SSIM: f64 = 0.95;
+
+/// The worst quality a texture type is allowed to be compressed to when
+/// picking block sizes automatically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct QualityTarget {
+    pub(crate) texture_type: TextureType,
+    /// The minimum PSNR in decibels, or for normal maps, the maximum mean
+    /// angle between the original and compressed normals in degrees.
+    pub(crate) threshold: f64,
+}
+
+impl QualityTarget {
+    pub(crate) fn default_for(texture_type: TextureType) -> Self {
+        let threshold = match texture_type {
+            TextureType::BaseColor | TextureType::Emissive => 38.0,
+            TextureType::MetallicRoughnessOcclusion => 40.0,
+            TextureType::Normal => 4.0,
+        };
+
+        QualityTarget {
+            texture_type,
+            threshold,
+        }
+    }
+
+    /// Whether a compressed texture is close enough to its source. Block
+    /// sizes are only picked for ASTC, so normal maps are always packed.
+    pub(crate) fn is_met(&self, source: &RgbaImage, compressed: &RgbaImage) -> bool {
+        match self.texture_type {
+            TextureType::Normal => mean_normal_error(source, compressed, true) <= self.threshold,
+            _ => psnr(source, compressed) >= self.threshold && ssim(source, compressed) >= MIN_SSIM,
+        }
+    }
+}
+
+impl FromStr for QualityTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (texture_type, threshold) = s
+            .split_once('=')
+            .with_context(|| format!("expected TYPE=VALUE, eg. 'normal=3', got '{s}'"))?;
+
+        Ok(QualityTarget {
+            texture_type: texture_type.parse()?,
+            threshold: threshold
+                .parse()
+                .with_context(|| format!("invalid quality threshold '{threshold}'"))?,
+        })
+    }
+}
+
+/// How close a compressed texture is to its source image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// In decibels. Infinite (or `null` in JSON) if nothing was lost.
+    #[serde(deserialize_with = "infinite_if_null")]
+    pub psnr: f64,
+    pub ssim: f64,
+    /// The largest difference in any channel of any pixel.
+    pub max_error: u8,
+    /// The mean angle between the original and compressed normals in
+    /// degrees. Only present for normal maps.
+    pub normal_error: Option<f64>,
+}
+
+impl Metrics {
+    /// Decodes the largest mip level of a compressed texture and compares it
+    /// to the image it was made from, resizing the source if the texture was
+    /// made from a resized copy. `packed_normals` says whether normal maps
+    /// were stored with X in the colour channels and Y in alpha.
+    pub(crate) fn measure(
+        source: &RgbaImage,
+        compressed: &[u8],
+        texture_type: TextureType,
+        packed_normals: bool,
+    ) -> anyhow::Result<Self> {
+        let compressed = decode_ktx2(compressed)?;
+        let mut source = source.clone();
+        if source.dimensions() != compressed.dimensions() {
+            source = image::imageops::resize(
+                &source,
+                compressed.width(),
+                compressed.height(),
+                image::imageops::Lanczos3,
+            );
+        }
+
+        if texture_type != TextureType::Normal {
+            return Ok(Metrics {
+                psnr: psnr(&source, &compressed),
+                ssim: ssim(&source, &compressed),
+                max_error: max_error(&source, &compressed),
+                normal_error: None,
+            });
+        }
+
+        // Compare normal maps the way they'll be used, after the shader has
+        // put the normal back together, ignoring alpha.
+        let normal_error = mean_normal_error(&source, &compressed, packed_normals);
+        let mut unpacked = compressed;
+        if packed_normals {
+            unpack_normal_map(&mut unpacked);
+        }
+        for pixel in source.pixels_mut().chain(unpacked.pixels_mut()) {
+            pixel[3] = 255;
+        }
+
+        Ok(Metrics {
+            psnr: psnr(&source, &unpacked),
+            ssim: ssim(&source, &unpacked),
+            max_error: max_error(&source, &unpacked),
+            normal_error: Some(normal_error),
+        })
+    }
+}
+
+/// Opens a KTX2 file, first checking that its data format descriptor and
+/// every mip level are within it. ktx2's `Reader` only checks the level that
+/// ends last, and panics when reading any of the others that don't fit.
+pub(crate) fn read_ktx2(bytes: &[u8]) -> anyhow::Result<ktx2::Reader<&[u8]>> {
+    const HEADER_LENGTH: usize = 80;
+    const LEVEL_INDEX_LENGTH: usize = 24;
+
+    // Anything shorter than a header is rejected by the reader.
+    if bytes.len() >= HEADER_LENGTH {
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as u64
+        };
+        ensure!(
+            u32_at(48) + u32_at(52) < bytes.len() as u64,
+            "KTX2 data format descriptor is out of bounds"
+        );
+
+        let levels = u32_at(40).max(1) as usize;
+        for level in 0..levels {
+            let start = HEADER_LENGTH + level * LEVEL_INDEX_LENGTH;
+            let index = bytes
+                .get(start..start + LEVEL_INDEX_LENGTH)
+                .context("KTX2 level index is truncated")?;
+            let field = |i: usize| u64::from_le_bytes(index[i * 8..i * 8 + 8].try_into().unwrap());
+            let end = field(0).checked_add(field(1));
+            ensure!(
+                end.map_or(false, |end| end <= bytes.len() as u64),
+                "KTX2 mip level {level} is out of bounds"
+            );
+        }
+    }
+
+    ktx2::Reader::new(bytes).context("invalid KTX2 file")
+}
+
+/// Decodes the largest mip level of a KTX2 image.
+pub fn decode_ktx2(bytes: &[u8]) -> anyhow::Result<RgbaImage> {
+    let reader = read_ktx2(bytes)?;
+    let header = reader.header();
+
+    let format = header.format.context("KTX2 image has no format")?;
+    let (width, height) = (header.pixel_width, header.pixel_height);
+    let level = reader.levels().next().context("KTX2 image has no levels")?;
+    let level = match header.supercompression_scheme {
+        None => level.to_vec(),
+        Some(ktx2::SupercompressionScheme::Zstandard) => {
+            let mut decoded = Vec::new();
+            ruzstd::StreamingDecoder::new(level)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+                .and_then(|mut decoder| Ok(decoder.read_to_end(&mut decoded)?))
+                .context("invalid Zstandard data in KTX2 image")?;
+            decoded
+        }
+        Some(scheme) => bail!("unable to decode KTX2 images supercompressed with {scheme:?}"),
+    };
+
+    let (block_width, block_height, _) = block_layout(format)
+        .with_context(|| format!("unable to decode KTX2 images in format {format:?}"))?;
+    if (block_width, block_height) == (1, 1) {
+        // Only RGBA8 is uncompressed, and B8G8R8A8 isn't something we write.
+        return RgbaImage::from_raw(width, height, level)
+            .context("KTX2 image is smaller than its dimensions");
+    }
+    if !matches!(format.0.get(), 157..=184) {
+        bail!("unable to decode KTX2 images in format {format:?}");
+    }
+
+    let mut image = RgbaImage::new(width, height);
+    let footprint = astc_decode::Footprint::new(block_width, block_height);
+    astc_decode::astc_decode(&level[..], width, height, footprint, |x, y, color| {
+        image.put_pixel(x, y, image::Rgba(color));
+    })
+    .context("ASTC data is truncated")?;
+
+    Ok(image)
+}
+
+/// The peak signal-to-noise ratio between two images, in decibels. Identical
+/// images are given a PSNR of infinity.
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let squared_error: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    let mean_squared_error = squared_error / a.as_raw().len().max(1) as f64;
+
+    10.0 * (255.0f64.powi(2) / mean_squared_error).log10()
+}
+
+/// The largest difference between any channel of any pixel in two images.
+pub fn max_error(a: &RgbaImage, b: &RgbaImage) -> u8 {
+    a.as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| a.abs_diff(b))
+        .max()
+        .unwrap_or_default()
+}
+
+/// The mean structural similarity of the luma of two images, measured over
+/// 8x8 windows. 1.0 means the images are identical.
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let luma = |pixel: &image::Rgba<u8>| {
+        0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+    };
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for window_y in (0..a.height()).step_by(WINDOW as usize) {
+        for window_x in (0..a.width()).step_by(WINDOW as usize) {
+            let mut pairs = Vec::new();
+            for y in window_y..(window_y + WINDOW).min(a.height()) {
+                for x in window_x..(window_x + WINDOW).min(a.width()) {
+                    pairs.push((luma(a.get_pixel(x, y)), luma(b.get_pixel(x, y))));
+                }
+            }
+
+            let n = pairs.len() as f64;
+            let mean_a = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+            let mean_b = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+            let (mut variance_a, mut variance_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (a, b) in &pairs {
+                variance_a += (a - mean_a).powi(2) / n;
+                variance_b += (b - mean_b).powi(2) / n;
+                covariance += (a - mean_a) * (b - mean_b) / n;
+            }
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows.max(1) as f64
+}
+
+/// The mean angle between the normals in a source normal map and the
+/// compressed version, in degrees.
+///
+/// When `packed`, the compressed normal map has X in the colour channels and
+/// Y in alpha, leaving Z to be reconstructed by the shader, the way `toktx`
+/// stores block compressed normal maps. Otherwise it's an ordinary one.
+pub fn mean_normal_error(source: &RgbaImage, compressed: &RgbaImage, packed: bool) -> f64 {
+    let normalize = |[x, y, z]: [f64; 3]| {
+        let length = (x * x + y * y + z * z).sqrt().max(f64::EPSILON);
+        [x / length, y / length, z / length]
+    };
+
+    let total: f64 = source
+        .pixels()
+        .zip(compressed.pixels())
+        .map(|(source, compressed)| {
+            let a = normalize([unpack(source[0]), unpack(source[1]), unpack(source[2])]);
+
+            let b = if packed {
+                let (x, y) = (unpack(compressed[0]), unpack(compressed[3]));
+                normalize([x, y, (1.0 - x * x - y * y).max(0.0).sqrt()])
+            } else {
+                normalize([
+                    unpack(compressed[0]),
+                    unpack(compressed[1]),
+                    unpack(compressed[2]),
+                ])
+            };
+
+            let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+            dot.clamp(-1.0, 1.0).acos().to_degrees()
+        })
+        .sum();
+
+    total / source.pixels().len().max(1) as f64
+}
+
+/// Turns a normal map in the layout `toktx` compresses them to back into an
+/// ordinary one, with X, Y and Z in the colour channels.
+pub fn unpack_normal_map(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let (x, y) = (unpack(pixel[0]), unpack(pixel[3]));
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        pixel[2] = ((z + 1.0) / 2.0 * 255.0).round() as u8;
+        pixel[1] = pixel[3];
+        pixel[3] = 255;
+    }
+}
+
+/// Infinite PSNRs are written to JSON as `null`, so read them back that way.
+fn infinite_if_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::INFINITY))
+}
+
+/// Turns a normal map channel into a value between -1 and 1.
+fn unpack(value: u8) -> f64 {
+    value as f64 / 255.0 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(32, 32, |x, y| {
+            image::Rgba([x as u8 * 8, y as u8 * 8, 128, 255])
+        })
+    }
+
+    #[test]
+    fn identical_images() {
+        let image = gradient();
+        assert_eq!(psnr(&image, &image), f64::INFINITY);
+        assert!((ssim(&image, &image) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn noisy_images() {
+        let image = gradient();
+        let mut noisy = image.clone();
+        for (i, pixel) in noisy.pixels_mut().enumerate() {
+            pixel[0] = pixel[0].saturating_add((i % 7) as u8 * 4);
+        }
+
+        let psnr = psnr(&image, &noisy);
+        assert!(psnr > 25.0 && psnr < 40.0, "{psnr}");
+        assert!(ssim(&image, &noisy) < 1.0);
+    }
+
+    #[test]
+    fn normal_errors() {
+        // Straight up, in both layouts.
+        let source = RgbaImage::from_pixel(4, 4, image::Rgba([128, 128, 255, 255]));
+        let compressed = RgbaImage::from_pixel(4, 4, image::Rgba([128, 128, 128, 128]));
+        assert!(mean_normal_error(&source, &compressed, true) < 1.0);
+        assert!(mean_normal_error(&source, &source, false) < 1.0);
+
+        // Tilted 45 degrees along X.
+        let compressed = RgbaImage::from_pixel(4, 4, image::Rgba([218, 218, 218, 128]));
+        let error = mean_normal_error(&source, &compressed, true);
+        assert!((error - 45.0).abs() < 1.0, "{error}");
+        let compressed = RgbaImage::from_pixel(4, 4, image::Rgba([218, 128, 218, 255]));
+        let error = mean_normal_error(&source, &compressed, false);
+        assert!((error - 45.0).abs() < 1.0, "{error}");
+
+        // Read as packed, an ordinary normal map looks far off.
+        assert!(mean_normal_error(&source, &source, true) > 45.0);
+    }
+
+    #[test]
+    fn parse_quality_targets() {
+        let target: QualityTarget = "normal=3".parse().unwrap();
+        assert_eq!(target.texture_type, TextureType::Normal);
+        assert_eq!(target.threshold, 3.0);
+
+        assert!("normal".parse::<QualityTarget>().is_err());
+        assert!("roughness=40".parse::<QualityTarget>().is_err());
+    }
+
+    /// Builds a single level, uncompressed KTX2 image.
+    pub fn rgba8_ktx2(image: &RgbaImage) -> Vec<u8> {
+        let mut ktx2 = vec![0; 80 + 24];
+        ktx2[..12].copy_from_slice(b"\xABKTX 20\xBB\r\n\x1A\n");
+        let header = [37u32, 1, image.width(), image.height(), 0, 0, 1, 1, 0];
+        for (i, value) in header.iter().enumerate() {
+            ktx2[12 + i * 4..16 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        // One level, straight after the level index.
+        let length = image.as_raw().len() as u64;
+        ktx2[80..88].copy_from_slice(&104u64.to_le_bytes());
+        ktx2[88..96].copy_from_slice(&length.to_le_bytes());
+        ktx2[96..104].copy_from_slice(&length.to_le_bytes());
+        ktx2.extend_from_slice(image.as_raw());
+        ktx2
+    }
+
+    #[test]
+    fn out_of_bounds_levels() {
+        let image = RgbaImage::new(4, 4);
+        let mut ktx2 = rgba8_ktx2(&image);
+        ktx2[40..44].copy_from_slice(&2u32.to_le_bytes());
+
+        // The first level moves along to make room for a second level index,
+        // which starts before it but runs past the end of the file.
+        let mut second = [0; 24];
+        second[..8].copy_from_slice(&104u64.to_le_bytes());
+        second[8..16].copy_from_slice(&1000u64.to_le_bytes());
+        ktx2.splice(104..104, second);
+        ktx2[80..88].copy_from_slice(&128u64.to_le_bytes());
+        assert_eq!(
+            decode_ktx2(&ktx2).unwrap_err().to_string(),
+            "KTX2 mip level 1 is out of bounds"
+        );
+
+        // Lengths that overflow aren't let through either.
+        ktx2[88..96].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            decode_ktx2(&ktx2).unwrap_err().to_string(),
+            "KTX2 mip level 0 is out of bounds"
+        );
+    }
+
+    #[test]
+    fn decode_rgba8() {
+        let image = RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 255, 0, 255]).unwrap();
+
+        let decoded = decode_ktx2(&rgba8_ktx2(&image)).unwrap();
+        assert_eq!(decoded, image);
+    }
+}
@@ -0,0 +1,188 @@
+//! Rewriting of the indices in a glTF document after some of its elements have
+//! been removed or merged together.
+
+use gltf::json::{Index, Root};
+
+/// Maps the old index of each element in one of the document's top-level
+/// arrays to its new index, or to `None` if the element is being removed.
+///
+/// Several elements may map to the same new index, in which case the first of
+/// them is kept and the rest are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct IndexMap(Vec<Option<u32>>);
+
+impl IndexMap {
+    /// A map that leaves every element where it is.
+    pub fn identity(len: usize) -> Self {
+        Self((0..len as u32).map(Some).collect())
+    }
+
+    /// A map that keeps the elements marked as used, in their original order,
+    /// and removes the rest.
+    pub fn retain(used: &[bool]) -> Self {
+        let mut next = 0;
+        let map = used
+            .iter()
+            .map(|&used| {
+                used.then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+
+        Self(map)
+    }
+
+    /// A map that replaces each element with the one at `canonical[index]`.
+    /// Each canonical element must come before any of its duplicates and be
+    /// its own canonical element.
+    pub fn merge(canonical: &[usize]) -> Self {
+        let mut new_indices = vec![None; canonical.len()];
+        let mut next = 0;
+        for (index, &canonical) in canonical.iter().enumerate() {
+            if index == canonical {
+                new_indices[index] = Some(next);
+                next += 1;
+            }
+        }
+
+        Self(canonical.iter().map(|&c| new_indices[c]).collect())
+    }
+
+    /// Returns the new index of an element, if it is being kept.
+    pub fn get<T>(&self, index: Index<T>) -> Option<Index<T>> {
+        self.0.get(index.value()).copied().flatten().map(Index::new)
+    }
+
+    /// Like [`IndexMap::get`], but for references that the document requires
+    /// to be present. The caller must not remove anything that a kept element
+    /// depends on.
+    fn required<T>(&self, index: Index<T>) -> Index<T> {
+        self.get(index)
+            .unwrap_or_else(|| panic!("index {} was removed while in use", index.value()))
+    }
+
+    /// How many elements will be left after applying this map.
+    fn len(&self) -> usize {
+        self.0
+            .iter()
+            .flatten()
+            .map(|&i| i as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        let mut slots: Vec<Option<T>> = std::iter::repeat_with(|| None).take(self.len()).collect();
+        for (item, new_index) in items.into_iter().zip(&self.0) {
+            if let Some(slot) = new_index.map(|i| &mut slots[i as usize]) {
+                if slot.is_none() {
+                    *slot = Some(item);
+                }
+            }
+        }
+
+        slots.into_iter().flatten().collect()
+    }
+}
+
+/// The changes to make to each of the arrays that can be compacted.
+#[derive(Debug, Clone, Default)]
+pub struct Remap {
+    pub accessors: IndexMap,
+    pub buffer_views: IndexMap,
+    pub images: IndexMap,
+    pub materials: IndexMap,
+    pub meshes: IndexMap,
+    pub samplers: IndexMap,
+    pub textures: IndexMap,
+}
+
+impl Remap {
+    /// Removes or merges elements of `root` and updates every reference to
+    /// them throughout the document.
+    pub fn apply(&self, root: &mut Root) {
+        // Drop the removed elements first, so that only the references held
+        // by the elements being kept need updating.
+        root.accessors = self.accessors.apply(std::mem::take(&mut root.accessors));
+        root.buffer_views = self
+            .buffer_views
+            .apply(std::mem::take(&mut root.buffer_views));
+        root.images = self.images.apply(std::mem::take(&mut root.images));
+        root.materials = self.materials.apply(std::mem::take(&mut root.materials));
+        root.meshes = self.meshes.apply(std::mem::take(&mut root.meshes));
+        root.samplers = self.samplers.apply(std::mem::take(&mut root.samplers));
+        root.textures = self.textures.apply(std::mem::take(&mut root.textures));
+
+        for node in &mut root.nodes {
+            node.mesh = node.mesh.and_then(|mesh| self.meshes.get(mesh));
+        }
+
+        for mesh in &mut root.meshes {
+            for primitive in &mut mesh.primitives {
+                for accessor in primitive.attributes.values_mut() {
+                    *accessor = self.accessors.required(*accessor);
+                }
+                primitive.indices = primitive.indices.map(|i| self.accessors.required(i));
+                primitive.material = primitive.material.and_then(|m| self.materials.get(m));
+
+                for target in primitive.targets.iter_mut().flatten() {
+                    target.positions = target.positions.map(|i| self.accessors.required(i));
+                    target.normals = target.normals.map(|i| self.accessors.required(i));
+                    target.tangents = target.tangents.map(|i| self.accessors.required(i));
+                }
+            }
+        }
+
+        for material in &mut root.materials {
+            let pbr = &mut material.pbr_metallic_roughness;
+            for texture in [
+                &mut pbr.base_color_texture,
+                &mut pbr.metallic_roughness_texture,
+                &mut material.emissive_texture,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                texture.index = self.textures.required(texture.index);
+            }
+            if let Some(texture) = &mut material.normal_texture {
+                texture.index = self.textures.required(texture.index);
+            }
+            if let Some(texture) = &mut material.occlusion_texture {
+                texture.index = self.textures.required(texture.index);
+            }
+        }
+
+        for texture in &mut root.textures {
+            texture.source = self.images.required(texture.source);
+            texture.sampler = texture.sampler.map(|s| self.samplers.required(s));
+        }
+
+        for image in &mut root.images {
+            image.buffer_view = image.buffer_view.map(|v| self.buffer_views.required(v));
+        }
+
+        for accessor in &mut root.accessors {
+            accessor.buffer_view = accessor.buffer_view.map(|v| self.buffer_views.required(v));
+            if let Some(sparse) = &mut accessor.sparse {
+                sparse.indices.buffer_view = self.buffer_views.required(sparse.indices.buffer_view);
+                sparse.values.buffer_view = self.buffer_views.required(sparse.values.buffer_view);
+            }
+        }
+
+        for skin in &mut root.skins {
+            skin.inverse_bind_matrices = skin
+                .inverse_bind_matrices
+                .map(|i| self.accessors.required(i));
+        }
+
+        for animation in &mut root.animations {
+            for sampler in &mut animation.samplers {
+                sampler.input = self.accessors.required(sampler.input);
+                sampler.output = self.accessors.required(sampler.output);
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! The size report printed after squishing, so asset budgets can be tracked.
+
+use std::{collections::BTreeMap, collections::HashMap, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    inspect::{format_bytes, Report, Totals},
+    quality::Metrics,
+    TextureOutcome,
+};
+
+/// How big the output is compared to the input.
+#[derive(Debug, Serialize)]
+pub struct SizeReport {
+    pub images: Vec<ImageSize>,
+    pub texture_types: Vec<TextureTypeSize>,
+    pub input: Totals,
+    pub output: Totals,
+    /// The size of the input, plus any buffers and images a `.gltf` refers
+    /// to by URI.
+    pub input_file_bytes: usize,
+    pub output_file_bytes: usize,
+    /// The input size divided by the output file size.
+    pub compression_ratio: f64,
+    /// Images that were left uncompressed by `--keep-going`.
+    pub failures: Vec<TextureFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageSize {
+    /// The index of the image in the output.
+    pub index: usize,
+    pub name: Option<String>,
+    /// Only present for images that we compressed.
+    pub texture_type: Option<String>,
+    pub input_bytes: usize,
+    pub output_bytes: usize,
+    /// Whether the compressed image came from the cache.
+    pub cached: bool,
+    /// How close the compressed image is to the original.
+    pub quality: Option<Metrics>,
+}
+
+/// An image that couldn't be compressed, so was left in its original format.
+#[derive(Debug, Clone, Serialize)]
+pub struct TextureFailure {
+    /// The index of the image in the output.
+    pub index: usize,
+    pub name: Option<String>,
+    pub texture_type: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextureTypeSize {
+    pub texture_type: String,
+    pub count: usize,
+    pub input_bytes: usize,
+    pub output_bytes: usize,
+}
+
+impl SizeReport {
+    /// Builds a report from the original input, the input after any
+    /// resources have been removed or merged (so its images line up with the
+    /// output's), and the output.
+    pub(crate) fn new(
+        original: &Report,
+        before: &Report,
+        after: &Report,
+        textures: &HashMap<usize, TextureOutcome>,
+        failures: &[TextureFailure],
+        input_file_bytes: usize,
+        output_file_bytes: usize,
+    ) -> Self {
+        let images: Vec<_> = after
+            .images
+            .iter()
+            .zip(&before.images)
+            .map(|(output, input)| {
+                let outcome = textures.get(&output.index);
+                ImageSize {
+                    index: output.index,
+                    name: output.name.clone(),
+                    texture_type: outcome.map(|o| format!("{:?}", o.texture_type)),
+                    input_bytes: input.bytes,
+                    output_bytes: output.bytes,
+                    cached: outcome.map_or(false, |o| o.cached),
+                    quality: outcome.and_then(|o| o.quality),
+                }
+            })
+            .collect();
+
+        let mut texture_types = BTreeMap::new();
+        for (image, outcome) in images.iter().filter_map(|image| {
+            textures
+                .get(&image.index)
+                .map(|outcome| (image, outcome.texture_type))
+        }) {
+            let size = texture_types
+                .entry(outcome)
+                .or_insert_with(|| TextureTypeSize {
+                    texture_type: format!("{outcome:?}"),
+                    count: 0,
+                    input_bytes: 0,
+                    output_bytes: 0,
+                });
+            size.count += 1;
+            size.input_bytes += image.input_bytes;
+            size.output_bytes += image.output_bytes;
+        }
+
+        SizeReport {
+            images,
+            texture_types: texture_types.into_values().collect(),
+            input: original.totals.clone(),
+            output: after.totals.clone(),
+            input_file_bytes,
+            output_file_bytes,
+            compression_ratio: input_file_bytes as f64 / output_file_bytes.max(1) as f64,
+            failures: failures.to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Size report:")?;
+        for image in &self.images {
+            let label = match &image.name {
+                Some(name) => format!("#{} \"{name}\"", image.index),
+                None => format!("#{}", image.index),
+            };
+            write!(
+                f,
+                "  {label:<24} {:<28} {:>10} -> {:>10}",
+                image.texture_type.as_deref().unwrap_or("-"),
+                format_bytes(image.input_bytes),
+                format_bytes(image.output_bytes),
+            )?;
+            if image.cached {
+                write!(f, " (cached)")?;
+            }
+            writeln!(f)?;
+
+            if let Some(quality) = &image.quality {
+                write!(
+                    f,
+                    "      PSNR {:.2} dB, SSIM {:.4}, max error {}",
+                    quality.psnr, quality.ssim, quality.max_error
+                )?;
+                if let Some(normal_error) = quality.normal_error {
+                    write!(f, ", normals off by {normal_error:.2}° on average")?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        for size in &self.texture_types {
+            writeln!(
+                f,
+                "  {:<24} {:<28} {:>10} -> {:>10}",
+                size.texture_type,
+                format!("{} images", size.count),
+                format_bytes(size.input_bytes),
+                format_bytes(size.output_bytes),
+            )?;
+        }
+
+        for (label, input, output) in [
+            ("images", self.input.images, self.output.images),
+            ("geometry", self.input.geometry, self.output.geometry),
+            ("JSON", self.input.json, self.output.json),
+            ("file", self.input_file_bytes, self.output_file_bytes),
+        ] {
+            writeln!(
+                f,
+                "  {label:<53} {:>10} -> {:>10}",
+                format_bytes(input),
+                format_bytes(output)
+            )?;
+        }
+
+        write!(f, "  compression ratio {:.2}:1", self.compression_ratio)?;
+
+        if !self.failures.is_empty() {
+            write!(f, "\n  left {} image(s) uncompressed:", self.failures.len())?;
+        }
+        for failure in &self.failures {
+            let label = match &failure.name {
+                Some(name) => format!("#{} \"{name}\"", failure.index),
+                None => format!("#{}", failure.index),
+            };
+            write!(
+                f,
+                "\n    {label:<22} {:<28} {}",
+                failure.texture_type, failure.error
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{open, TextureType};
+
+    #[test]
+    fn sizes_by_texture_type() {
+        let input = open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let report = Report::new(&input).unwrap();
+        let textures = HashMap::from([(
+            0,
+            TextureOutcome {
+                texture_type: TextureType::BaseColor,
+                cached: true,
+                quality: Some(Metrics {
+                    psnr: 42.0,
+                    ssim: 0.99,
+                    max_error: 9,
+                    normal_error: None,
+                }),
+            },
+        )]);
+
+        let size = SizeReport::new(&report, &report, &report, &textures, &[], 6556, 3278);
+
+        assert_eq!(size.images.len(), 1);
+        assert!(size.images[0].cached);
+        assert_eq!(size.images[0].texture_type.as_deref(), Some("BaseColor"));
+        assert_eq!(size.texture_types.len(), 1);
+        assert_eq!(size.texture_types[0].count, 1);
+        assert_eq!(size.texture_types[0].input_bytes, report.totals.images);
+        assert_eq!(size.compression_ratio, 2.0);
+        assert_eq!(size.images[0].quality.unwrap().max_error, 9);
+        assert!(size
+            .to_string()
+            .contains("PSNR 42.00 dB, SSIM 0.9900, max error 9"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Keeping squished files byte-for-byte the same for the same input and
+//! settings, so they can be diffed in version control and served from
+//! content-addressed storage.
+//!
+//! Padding is always zeroes (or spaces, in the JSON chunk), and objects keep
+//! the order they had in the input. Two things would still vary: the keys of
+//! maps in the JSON, like a primitive's attributes, come out in hash order,
+//! and `toktx` adds key/value pairs saying which build of it wrote each
+//! texture. The first are sorted and the second are removed.
+
+use anyhow::{bail, ensure, Context};
+
+/// Key/value pairs saying which program wrote a KTX2 file, and with which
+/// options.
+const WRITER_KEYS: [&[u8]; 2] = [b"KTXwriter", b"KTXwriterScParams"];
+
+const KTX2_MAGIC: &[u8] = b"\xabKTX 20\xbb\r\n\x1a\n";
+
+/// Where the index of a KTX2 file's sections starts, after the identifier
+/// and the fixed size header.
+const INDEX_OFFSET: usize = 48;
+const LEVEL_INDEX_OFFSET: usize = 80;
+
+/// Everything after the key/value data is moved by a multiple of this, so
+/// supercompression data and mip levels stay as aligned as they were.
+const ALIGNMENT: usize = 16;
+
+/// Serializes a document with the keys of every object in sorted order, so
+/// maps come out the same way every time.
+pub fn to_json(root: &gltf::json::Root) -> anyhow::Result<String> {
+    // Without serde_json's `preserve_order` feature, its objects are
+    // B-tree maps, which serialize in key order. Going through the text
+    // rather than `to_value` keeps `f32`s from being widened, which would
+    // turn 0.8 into 0.800000011920929.
+    let json = gltf::json::serialize::to_string(root)?;
+    let value: gltf::json::Value = gltf::json::deserialize::from_str(&json)?;
+    Ok(gltf::json::serialize::to_string(&value)?)
+}
+
+/// Removes the key/value pairs that say which program wrote a KTX2 file,
+/// moving the data after them back to take up the space.
+pub fn strip_writer(ktx2: &[u8]) -> anyhow::Result<Vec<u8>> {
+    ensure!(ktx2.starts_with(KTX2_MAGIC), "not a KTX2 file");
+    let level_count = (read_u32(ktx2, 40)? as usize).max(1);
+    let dfd_end = read_u32(ktx2, INDEX_OFFSET)? as usize + read_u32(ktx2, 52)? as usize;
+    let kvd_offset = read_u32(ktx2, 56)? as usize;
+    let kvd_length = read_u32(ktx2, 60)? as usize;
+    let sgd_offset = read_u64(ktx2, 64)? as usize;
+    let sgd_length = read_u64(ktx2, 72)? as usize;
+    if kvd_length == 0 {
+        return Ok(ktx2.to_vec());
+    }
+
+    let kvd = ktx2
+        .get(kvd_offset..kvd_offset + kvd_length)
+        .context("key/value data is out of bounds")?;
+    let entries = key_values(kvd)?;
+    let kept: Vec<_> = entries
+        .iter()
+        .filter(|(key, _)| !WRITER_KEYS.contains(key))
+        .collect();
+    if kept.len() == entries.len() {
+        return Ok(ktx2.to_vec());
+    }
+
+    // Everything else comes after the key/value data, in the order the
+    // spec gives: supercompression data, then the mip levels.
+    let level_offsets: Vec<usize> = (0..level_count)
+        .map(|level| Ok(read_u64(ktx2, LEVEL_INDEX_OFFSET + level * 24)? as usize))
+        .collect::<anyhow::Result<_>>()?;
+    let rest = match sgd_length {
+        0 => level_offsets.iter().copied().min(),
+        _ => Some(sgd_offset),
+    }
+    .context("no mip levels")?;
+    if kvd_offset < dfd_end || rest < kvd_offset + kvd_length || rest > ktx2.len() {
+        bail!("sections aren't in the usual order");
+    }
+
+    let mut output = ktx2[..kvd_offset].to_vec();
+    for (key, value) in &kept {
+        let length = key.len() + 1 + value.len();
+        output.extend_from_slice(&(length as u32).to_le_bytes());
+        output.extend_from_slice(key);
+        output.push(0);
+        output.extend_from_slice(value);
+        pad(&mut output, 4);
+    }
+    let new_kvd_length = output.len() - kvd_offset;
+
+    // Keep the rest at the same offset modulo the alignment, so nothing
+    // after it needs any more or less padding than it had.
+    while output.len() % ALIGNMENT != rest % ALIGNMENT {
+        output.push(0);
+    }
+    let shift = rest - output.len();
+    output.extend_from_slice(&ktx2[rest..]);
+
+    let new_kvd_offset = if new_kvd_length == 0 { 0 } else { kvd_offset };
+    output[56..60].copy_from_slice(&(new_kvd_offset as u32).to_le_bytes());
+    output[60..64].copy_from_slice(&(new_kvd_length as u32).to_le_bytes());
+    if sgd_length > 0 {
+        output[64..72].copy_from_slice(&((sgd_offset - shift) as u64).to_le_bytes());
+    }
+    for (level, offset) in level_offsets.into_iter().enumerate() {
+        let start = LEVEL_INDEX_OFFSET + level * 24;
+        output[start..start + 8].copy_from_slice(&((offset - shift) as u64).to_le_bytes());
+    }
+
+    Ok(output)
+}
+
+/// Splits key/value data into its keys and values, without the NUL after
+/// each key.
+fn key_values(mut kvd: &[u8]) -> anyhow::Result<Vec<(&[u8], &[u8])>> {
+    let mut entries = Vec::new();
+    while kvd.len() >= 4 {
+        let length = read_u32(kvd, 0)? as usize;
+        let entry = kvd
+            .get(4..4 + length)
+            .context("key/value pair is out of bounds")?;
+        let nul = entry
+            .iter()
+            .position(|b| *b == 0)
+            .context("key isn't NUL terminated")?;
+        entries.push((&entry[..nul], &entry[nul + 1..]));
+
+        let padded = (4 + length + 3) & !3;
+        kvd = kvd.get(padded..).unwrap_or_default();
+    }
+    Ok(entries)
+}
+
+fn pad(bytes: &mut Vec<u8>, alignment: usize) {
+    while bytes.len() % alignment != 0 {
+        bytes.push(0);
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> anyhow::Result<u32> {
+    let bytes = bytes
+        .get(offset..offset + 4)
+        .context("KTX2 file is truncated")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> anyhow::Result<u64> {
+    let bytes = bytes
+        .get(offset..offset + 8)
+        .context("KTX2 file is truncated")?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x4 RGBA8 KTX2 file with one mip level and the given key/value
+    /// pairs, laid out the way `toktx` does it.
+    fn ktx2(key_values: &[(&str, &str)]) -> Vec<u8> {
+        let dfd = 4u32.to_le_bytes();
+        let dfd_offset = LEVEL_INDEX_OFFSET + 24;
+        let kvd_offset = dfd_offset + dfd.len();
+        let mut kvd = Vec::new();
+        for (key, value) in key_values {
+            kvd.extend_from_slice(&(key.len() as u32 + 1 + value.len() as u32).to_le_bytes());
+            kvd.extend_from_slice(key.as_bytes());
+            kvd.push(0);
+            kvd.extend_from_slice(value.as_bytes());
+            pad(&mut kvd, 4);
+        }
+        let level_offset = (kvd_offset + kvd.len() + 15) & !15;
+
+        let mut file = KTX2_MAGIC.to_vec();
+        for value in [37, 1, 4, 4, 0, 0, 1, 1, 0] {
+            file.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        let kvd_offset = if kvd.is_empty() { 0 } else { kvd_offset };
+        for value in [dfd_offset, dfd.len(), kvd_offset, kvd.len()] {
+            file.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        file.extend_from_slice(&[0; 16]);
+        for value in [level_offset, 64, 64] {
+            file.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        file.extend_from_slice(&dfd);
+        file.extend_from_slice(&kvd);
+        pad(&mut file, 16);
+        file.extend((0..64).map(|i| i as u8));
+        file
+    }
+
+    #[test]
+    fn strips_writer() {
+        let written = ktx2(&[
+            ("KTXorientation", "rd"),
+            ("KTXwriter", "toktx v4.1.0 / libktx v4.1.0"),
+            ("KTXwriterScParams", "--astc_blk_d 6x6"),
+        ]);
+        let stripped = strip_writer(&written).unwrap();
+        assert_eq!(stripped, ktx2(&[("KTXorientation", "rd")]));
+
+        let reader = ktx2::Reader::new(&stripped).unwrap();
+        assert_eq!(
+            reader.levels().next().unwrap(),
+            (0..64).map(|i| i as u8).collect::<Vec<_>>()
+        );
+
+        // Two different builds of toktx give the same texture.
+        let other = ktx2(&[("KTXwriter", "toktx v4.3.2~1 / libktx v4.3.2~1")]);
+        assert_eq!(strip_writer(&other).unwrap(), ktx2(&[]));
+        assert_eq!(strip_writer(&ktx2(&[])).unwrap(), ktx2(&[]));
+    }
+
+    #[test]
+    fn sorted_json() {
+        let root: gltf::json::Root = gltf::json::deserialize::from_str(
+            r#"{
+                "asset": {"version": "2.0"},
+                "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [0.8, 0.1, 1e-30, 1]}}],
+                "meshes": [{"primitives": [{"attributes": {"TEXCOORD_0": 2, "POSITION": 0, "NORMAL": 1}}]}]
+            }"#,
+        )
+        .unwrap();
+        // The attributes are sorted, and the factors are written the way
+        // they were read rather than widened to f64.
+        assert_eq!(
+            to_json(&root).unwrap(),
+            concat!(
+                r#"{"asset":{"version":"2.0"},"#,
+                r#""materials":[{"alphaMode":"OPAQUE","doubleSided":false,"emissiveFactor":[0.0,0.0,0.0],"#,
+                r#""pbrMetallicRoughness":{"baseColorFactor":[0.8,0.1,1e-30,1.0],"metallicFactor":1.0,"roughnessFactor":1.0}}],"#,
+                r#""meshes":[{"primitives":[{"attributes":{"NORMAL":1,"POSITION":0,"TEXCOORD_0":2}}]}]}"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,702 @@
+//! `squisher serve`, which keeps squisher running and takes requests over
+//! JSON-RPC, so editors that squish on every save don't pay for starting it
+//! up each time.
+//!
+//! Requests and responses are JSON-RPC 2.0, one per line, on stdin and stdout
+//! or on a Unix socket:
+//!
+//! ```json
+//! {"jsonrpc":"2.0","id":1,"method":"squish","params":{"input":"model.glb","output":"out.glb","options":["--profile","quest2"]}}
+//! {"jsonrpc":"2.0","id":2,"method":"cancel","params":{"id":1}}
+//! {"jsonrpc":"2.0","id":3,"method":"cache_stats"}
+//! ```
+//!
+//! Squishes run on a pool of workers, each of which keeps the images it has
+//! decoded and resized between requests, and `toktx` is only checked again
+//! for requests whose config points at a different one.
+//!
+//! `cache_stats` gives the stats of every cache the server has used, starting
+//! with the one its own config names.
+
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches};
+use gltf::json::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::{cache, config, error::SquishError, report::SizeReport, toktx, Args, Cli, Session};
+
+// The error codes JSON-RPC reserves for problems with the request itself.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+#[derive(clap::Args)]
+pub struct ServeArgs {
+    /// Listen on this Unix socket instead of stdin and stdout.
+    #[clap(long)]
+    socket: Option<PathBuf>,
+
+    /// How many files to squish at once. Defaults to the number of CPUs.
+    #[clap(long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Read settings from this file instead of the squisher.toml closest to
+    /// the current directory.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// How many seconds toktx gets to compress each texture before it's
+    /// killed, unless a request gives its own `--toktx-timeout`.
+    #[clap(long, default_value_t = 600)]
+    toktx_timeout: u64,
+
+    /// Enables more verbose logging.
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Missing for notifications, which don't get a response.
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct Response<'a> {
+    jsonrpc: &'static str,
+    id: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+/// The parameters of `squish`. Either `input` or `data` has to be given.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SquishParams {
+    /// The path of the file to squish.
+    input: Option<PathBuf>,
+    /// A GLB, or a glTF with everything embedded, as base64.
+    data: Option<String>,
+    /// Where to write the output. Without it, the output is sent back as
+    /// base64, which is only possible with `data`.
+    output: Option<PathBuf>,
+    /// Command line options, eg. `["--profile", "quest2"]`.
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CancelParams {
+    /// The id of the `squish` request to cancel.
+    id: Value,
+}
+
+#[derive(Serialize)]
+struct SquishResult {
+    outputs: Vec<OutputResult>,
+}
+
+#[derive(Serialize)]
+struct OutputResult {
+    /// Where the output was written, unless it's in `data`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    /// The output as base64, if no output path was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    report: SizeReport,
+}
+
+/// Somewhere to send responses. Workers and the connection share it.
+type Client = Arc<Mutex<dyn Write + Send>>;
+
+/// A `squish` request waiting for, or being run by, a worker.
+struct Job {
+    client: Client,
+    id: Value,
+    key: JobKey,
+    args: Args,
+    /// The temporary directory holding the input and output, if they were
+    /// sent as base64.
+    scratch: Option<tempfile::TempDir>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Which connection a job came from, and its request id, so one client
+/// can't cancel another's jobs. Notifications, which have no id, get one
+/// that no request can have.
+type JobKey = (usize, String);
+
+#[derive(Default)]
+struct Jobs {
+    queued: VecDeque<Job>,
+    running: HashMap<JobKey, Arc<AtomicBool>>,
+    /// Set once there won't be any more jobs.
+    closed: bool,
+}
+
+struct Server {
+    jobs: Mutex<Jobs>,
+    /// Signalled when a job is queued, or the server is closed.
+    changed: Condvar,
+    /// How many notifications have been queued, to give each its own key.
+    notifications: AtomicUsize,
+    toktx: toktx::Toktx,
+    /// The caches jobs have used, starting with the one the server's config
+    /// names.
+    caches: Arc<cache::Caches>,
+}
+
+pub fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    crate::configure_logging(args.verbose);
+
+    let config = match &args.config {
+        Some(path) => Some(config::Config::load(path)?),
+        None => match config::find_in(&std::env::current_dir()?) {
+            Some(path) => Some(config::Config::load(&path)?),
+            None => None,
+        },
+    };
+    let toktx = config.as_ref().and_then(|config| config.toktx.as_deref());
+    let timeout = Duration::from_secs(args.toktx_timeout);
+    let toktx = toktx::Toktx::find(toktx, timeout).context(SquishError::ToktxUnavailable)?;
+
+    let caches = Arc::new(cache::Caches::default());
+    caches.open(&config.map(|config| config.cache).unwrap_or_default())?;
+    let server = Arc::new(Server {
+        jobs: Mutex::default(),
+        changed: Condvar::new(),
+        notifications: AtomicUsize::new(0),
+        toktx,
+        caches,
+    });
+
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let workers: Vec<_> = (0..jobs)
+        .map(|_| {
+            let server = server.clone();
+            thread::spawn(move || work(&server))
+        })
+        .collect();
+
+    match &args.socket {
+        Some(path) => listen(&server, path)?,
+        None => {
+            log::info!("Serving on stdin and stdout with {jobs} worker(s)");
+            let client: Client = Arc::new(Mutex::new(io::stdout()));
+            read_requests(&server, 0, io::stdin().lock(), &client);
+        }
+    }
+
+    // Let the workers finish what's already been asked for.
+    server.jobs.lock().unwrap().closed = true;
+    server.changed.notify_all();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn listen(server: &Arc<Server>, path: &std::path::Path) -> anyhow::Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // A socket left behind by a server that's gone away would stop us
+    // binding, but one that's still answering is in use.
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!("another server is already listening on {}", path.display());
+        }
+        fs_err::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+    log::info!("Serving on {}", path.display());
+
+    for (connection, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("Failed to accept a connection: {err}");
+                continue;
+            }
+        };
+        let server = server.clone();
+        thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Ok(reader) => BufReader::new(reader),
+                Err(err) => return log::warn!("Failed to read from a connection: {err}"),
+            };
+            let client: Client = Arc::new(Mutex::new(stream));
+            read_requests(&server, connection + 1, reader, &client);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen(_: &Arc<Server>, _: &std::path::Path) -> anyhow::Result<()> {
+    bail!("--socket is only supported on Unix, use stdin and stdout instead")
+}
+
+/// Handles requests from one client until it hangs up.
+fn read_requests(server: &Server, connection: usize, reader: impl BufRead, client: &Client) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                log::warn!("Failed to read request: {err}");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match gltf::json::deserialize::from_str(&line) {
+            Ok(request) => request,
+            Err(err) => {
+                let code = match gltf::json::deserialize::from_str::<Value>(&line) {
+                    Ok(_) => INVALID_REQUEST,
+                    Err(_) => PARSE_ERROR,
+                };
+                respond(client, &Value::Null, Err(rpc_error(code, err)));
+                continue;
+            }
+        };
+
+        let id = request.id.unwrap_or(Value::Null);
+        let result = if request.jsonrpc != "2.0" {
+            Err(rpc_error(INVALID_REQUEST, "jsonrpc must be \"2.0\""))
+        } else {
+            match request.method.as_str() {
+                "squish" => match queue_squish(server, connection, client, &id, request.params) {
+                    // The worker responds once it's done.
+                    Ok(()) => continue,
+                    Err(err) => Err(err),
+                },
+                "cancel" => params(request.params).map(|params: CancelParams| {
+                    let key = (connection, id_key(&params.id));
+                    Value::Bool(cancel(server, &key))
+                }),
+                "cache_stats" => server
+                    .caches
+                    .stats()
+                    .and_then(|stats| Ok(gltf::json::serialize::to_value(stats)?))
+                    .map_err(squish_error),
+                method => Err(rpc_error(
+                    METHOD_NOT_FOUND,
+                    format!("unknown method '{method}'"),
+                )),
+            }
+        };
+
+        if !id.is_null() {
+            respond(client, &id, result);
+        }
+    }
+}
+
+fn queue_squish(
+    server: &Server,
+    connection: usize,
+    client: &Client,
+    id: &Value,
+    params: Option<Value>,
+) -> Result<(), RpcError> {
+    let params: SquishParams = self::params(params)?;
+    let timeout = server.toktx.timeout();
+    let (args, scratch) = squish_args(params, timeout).map_err(|e| rpc_error(INVALID_PARAMS, e))?;
+
+    let key = match id.is_null() {
+        true => {
+            let notification = server.notifications.fetch_add(1, Ordering::Relaxed);
+            (connection, format!("#{notification}"))
+        }
+        false => (connection, id_key(id)),
+    };
+
+    // Jobs are cancelled and tracked by their ids, so they can't be shared.
+    let mut jobs = server.jobs.lock().unwrap();
+    if jobs.running.contains_key(&key) || jobs.queued.iter().any(|job| job.key == key) {
+        return Err(rpc_error(
+            INVALID_REQUEST,
+            format!("a squish with id {id} is already queued or running"),
+        ));
+    }
+    jobs.queued.push_back(Job {
+        client: client.clone(),
+        id: id.clone(),
+        key,
+        args,
+        scratch,
+        cancelled: Arc::default(),
+    });
+    drop(jobs);
+    server.changed.notify_one();
+    Ok(())
+}
+
+/// Works out the arguments for a `squish`, writing the input to a temporary
+/// directory if it was sent as base64. The request's options can override the
+/// server's `--toktx-timeout`.
+fn squish_args(
+    params: SquishParams,
+    toktx_timeout: Duration,
+) -> anyhow::Result<(Args, Option<tempfile::TempDir>)> {
+    let mut scratch = None;
+    let (input, output) = match (params.input, params.data) {
+        (Some(input), None) => {
+            let output = params
+                .output
+                .context("output is needed when squishing a file")?;
+            (input, output)
+        }
+        (None, Some(data)) => {
+            let data = base64::decode(data).context("data isn't valid base64")?;
+            let dir = tempfile::tempdir()?;
+            let extension = match data.starts_with(b"glTF") {
+                true => "glb",
+                false => "gltf",
+            };
+            let input = dir.path().join(format!("input.{extension}"));
+            fs_err::write(&input, data)?;
+            let output = params
+                .output
+                .unwrap_or_else(|| dir.path().join("output.glb"));
+            scratch = Some(dir);
+            (input, output)
+        }
+        _ => bail!("exactly one of input and data must be given"),
+    };
+
+    let matches = Cli::command().try_get_matches_from(
+        [
+            "squisher".into(),
+            input.into_os_string(),
+            output.into_os_string(),
+        ]
+        .into_iter()
+        .chain(params.options.into_iter().map(Into::into)),
+    )?;
+    let Some(mut args) = Cli::from_arg_matches(&matches)?.squish else {
+        bail!("options can only be options for squishing");
+    };
+    if args.watch || args.progress.is_some() {
+        bail!("--watch and --progress can't be used when serving");
+    }
+    if matches.value_source("toktx_timeout") == Some(ValueSource::DefaultValue) {
+        args.toktx_timeout = toktx_timeout.as_secs();
+    }
+    Ok((args, scratch))
+}
+
+/// Takes jobs off the queue and squishes them until the server closes.
+fn work(server: &Server) {
+    let toktx = Rc::new(server.toktx.clone());
+    let mut sources = Rc::default();
+
+    while let Some(job) = next_job(server) {
+        let session = Session {
+            sources: Rc::clone(&sources),
+            toktx: Some(toktx.clone()),
+            caches: server.caches.clone(),
+            cancelled: job.cancelled.clone(),
+        };
+        // A bug that panics fails the request rather than taking the worker,
+        // and the job's `running` entry, with it.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::squish_targets(&job.args, &session)
+                .and_then(|outputs| squish_result(&job, outputs))
+        }));
+        let result = match result {
+            Ok(result) => {
+                session.sources.forget_unused();
+                result.map_err(|err| {
+                    log::error!("Failed to squish {}: {err:?}", job.args.input.display());
+                    squish_error(err)
+                })
+            }
+            Err(payload) => {
+                // The images might have been left half updated.
+                sources = Rc::default();
+                let message = panic_message(&*payload);
+                log::error!("Panicked squishing {}: {message}", job.args.input.display());
+                Err(rpc_error(1, format!("squisher panicked: {message}")))
+            }
+        };
+
+        server.jobs.lock().unwrap().running.remove(&job.key);
+        if !job.id.is_null() {
+            respond(&job.client, &job.id, result);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("unknown", String::as_str),
+    }
+}
+
+fn next_job(server: &Server) -> Option<Job> {
+    let mut jobs = server.jobs.lock().unwrap();
+    loop {
+        if let Some(job) = jobs.queued.pop_front() {
+            jobs.running.insert(job.key.clone(), job.cancelled.clone());
+            return Some(job);
+        }
+        if jobs.closed {
+            return None;
+        }
+        jobs = server.changed.wait(jobs).unwrap();
+    }
+}
+
+fn squish_result(job: &Job, outputs: Vec<crate::Output>) -> anyhow::Result<Value> {
+    let outputs = outputs
+        .into_iter()
+        .map(|output| {
+            let in_scratch = job
+                .scratch
+                .as_ref()
+                .map_or(false, |dir| output.path.starts_with(dir.path()));
+            Ok(match in_scratch {
+                true => OutputResult {
+                    path: None,
+                    data: Some(base64::encode(fs_err::read(&output.path)?)),
+                    report: output.report,
+                },
+                false => OutputResult {
+                    path: Some(output.path),
+                    data: None,
+                    report: output.report,
+                },
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(gltf::json::serialize::to_value(SquishResult { outputs })?)
+}
+
+/// Cancels a job, whether it's waiting or already running. Returns whether
+/// there was one to cancel.
+fn cancel(server: &Server, key: &JobKey) -> bool {
+    let mut jobs = server.jobs.lock().unwrap();
+    if let Some(position) = jobs.queued.iter().position(|job| &job.key == key) {
+        let job = jobs.queued.remove(position).unwrap();
+        drop(jobs);
+        if !job.id.is_null() {
+            let error = squish_error(SquishError::Cancelled.into());
+            respond(&job.client, &job.id, Err(error));
+        }
+        return true;
+    }
+
+    match jobs.running.get(key) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    let params = params.unwrap_or_else(|| Value::Object(Default::default()));
+    gltf::json::deserialize::from_value(params).map_err(|e| rpc_error(INVALID_PARAMS, e))
+}
+
+/// Request ids can be numbers or strings, so they're compared as JSON, which
+/// never starts with the `#` of a notification's key.
+fn id_key(id: &Value) -> String {
+    id.to_string()
+}
+
+fn rpc_error(code: i32, message: impl ToString) -> RpcError {
+    RpcError {
+        code,
+        message: message.to_string(),
+    }
+}
+
+/// Failures to squish use the same codes as squisher's exit status.
+fn squish_error(err: anyhow::Error) -> RpcError {
+    let code = SquishError::find(&err).map_or(1, SquishError::exit_code);
+    rpc_error(code, format!("{err:#}"))
+}
+
+fn respond(client: &Client, id: &Value, result: Result<Value, RpcError>) {
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    let response = Response {
+        jsonrpc: "2.0",
+        id,
+        result,
+        error,
+    };
+
+    // A client that's hung up doesn't stop the server.
+    let mut client = client.lock().unwrap();
+    if let Err(err) = gltf::json::serialize::to_writer(&mut *client, &response)
+        .map_err(io::Error::from)
+        .and_then(|()| writeln!(client))
+        .and_then(|()| client.flush())
+    {
+        log::warn!("Failed to send response: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_server() -> Server {
+        Server {
+            jobs: Mutex::default(),
+            changed: Condvar::new(),
+            notifications: AtomicUsize::new(0),
+            toktx: toktx::Toktx::find(None, Duration::from_secs(60)).unwrap(),
+            caches: Arc::default(),
+        }
+    }
+
+    /// Runs requests through a server with one worker, returning the
+    /// responses.
+    fn run(requests: &str) -> Vec<Value> {
+        let server = test_server();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let client: Client = output.clone();
+
+        thread::scope(|scope| {
+            scope.spawn(|| work(&server));
+            read_requests(&server, 0, requests.as_bytes(), &client);
+            server.jobs.lock().unwrap().closed = true;
+            server.changed.notify_all();
+        });
+
+        let output = output.lock().unwrap();
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(|line| gltf::json::deserialize::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn squish_requests() {
+        let data = base64::encode(fs_err::read("test_data/BoxTexturedBinary.glb").unwrap());
+        let requests = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"squish","params":{"input":"test_data/BoxTexturedBinary.glb","output":"test_output/serve.glb","options":["--no-cache"]}}"#.to_string(),
+            format!(r#"{{"jsonrpc":"2.0","id":"bytes","method":"squish","params":{{"data":"{data}","options":["--no-cache","--format","rgba8"]}}}}"#),
+            r#"{"jsonrpc":"2.0","id":3,"method":"squish","params":{"output":"test_output/serve.glb"}}"#.to_string(),
+            r#"{"jsonrpc":"2.0","id":4,"method":"cancel","params":{"id":99}}"#.to_string(),
+            r#"{"jsonrpc":"2.0","id":5,"method":"explode"}"#.to_string(),
+            r#"{"jsonrpc":"2.0","id":6,"method":"cache_stats"}"#.to_string(),
+            "not json".to_string(),
+        ];
+        fs_err::create_dir_all("test_output").unwrap();
+        let responses = run(&requests.join("\n"));
+
+        let response = |id: Value| {
+            responses
+                .iter()
+                .find(|response| response["id"] == id)
+                .unwrap_or_else(|| panic!("no response to {id}"))
+        };
+
+        let file = &response(1.into())["result"]["outputs"][0];
+        assert_eq!(file["path"], "test_output/serve.glb");
+        assert_eq!(file["report"]["images"][0]["texture_type"], "BaseColor");
+
+        let bytes = &response("bytes".into())["result"]["outputs"][0];
+        let glb = base64::decode(bytes["data"].as_str().unwrap()).unwrap();
+        assert!(crate::Input::from_glb(&glb, PathBuf::new()).is_ok());
+
+        assert_eq!(response(3.into())["error"]["code"], INVALID_PARAMS);
+        assert_eq!(response(4.into())["result"], false);
+        assert_eq!(response(5.into())["error"]["code"], METHOD_NOT_FOUND);
+        // Neither squish used the cache.
+        assert_eq!(response(6.into())["result"], Value::Array(Vec::new()));
+        assert_eq!(response(Value::Null)["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn toktx_timeout() {
+        let params = |options: &[&str]| SquishParams {
+            input: Some("model.glb".into()),
+            data: None,
+            output: Some("out.glb".into()),
+            options: options.iter().map(|option| option.to_string()).collect(),
+        };
+        let server = Duration::from_secs(30);
+
+        let (args, _) = squish_args(params(&[]), server).unwrap();
+        assert_eq!(args.toktx_timeout, 30);
+        let (args, _) = squish_args(params(&["--toktx-timeout", "5"]), server).unwrap();
+        assert_eq!(args.toktx_timeout, 5);
+    }
+
+    #[test]
+    fn unique_job_ids() {
+        let server = test_server();
+        let client: Client = Arc::new(Mutex::new(Vec::new()));
+        let params = || {
+            let params = r#"{"input":"model.glb","output":"out.glb"}"#;
+            Some(gltf::json::deserialize::from_str(params).unwrap())
+        };
+
+        // Nothing's running the jobs, so the first is still queued.
+        assert!(queue_squish(&server, 0, &client, &1.into(), params()).is_ok());
+        let error = queue_squish(&server, 0, &client, &1.into(), params()).unwrap_err();
+        assert_eq!(error.code, INVALID_REQUEST);
+        // Other connections have ids of their own.
+        assert!(queue_squish(&server, 1, &client, &1.into(), params()).is_ok());
+
+        // Notifications can't clash with each other or with requests.
+        for _ in 0..2 {
+            assert!(queue_squish(&server, 0, &client, &Value::Null, params()).is_ok());
+        }
+        let jobs = server.jobs.lock().unwrap();
+        assert_eq!(jobs.queued.len(), 4);
+    }
+}
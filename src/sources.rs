@@ -0,0 +1,209 @@
+//! Source images shared between every target squished in one run, so each
+//! one is only decoded and resized once no matter how many outputs use it.
+//! Decoded images take a lot of memory, so only the most recently used are
+//! kept.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    hash::Hasher,
+    io,
+    rc::Rc,
+};
+
+use anyhow::Context;
+use image::{codecs::png::PngEncoder, ImageEncoder, RgbaImage};
+
+/// Encoded image bytes, shared rather than copied for each target.
+type Bytes = Rc<Vec<u8>>;
+
+/// How much decoded image data to keep around, enough for four 4K textures.
+/// Decoded images are big, and `squisher serve` has a `Sources` per worker.
+const MAX_DECODED_BYTES: usize = 256 << 20;
+
+pub struct Sources {
+    /// Images that were too big, shrunk and re-encoded as PNG, by the hash of
+    /// the original and the size they were shrunk to fit.
+    resized: RefCell<HashMap<(u64, u32), Bytes>>,
+    /// Decoded images, and when they were last used, by the hash of their
+    /// encoded bytes.
+    decoded: RefCell<HashMap<u64, (Rc<RgbaImage>, u64)>>,
+    /// Once the decoded images are bigger than this, the least recently used
+    /// ones are dropped.
+    max_decoded_bytes: usize,
+    /// Counts up every time a decoded image is used.
+    clock: Cell<u64>,
+    /// The hashes of the images used since `forget_unused` was last called.
+    used: RefCell<HashSet<u64>>,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources {
+            resized: Default::default(),
+            decoded: Default::default(),
+            max_decoded_bytes: MAX_DECODED_BYTES,
+            clock: Default::default(),
+            used: Default::default(),
+        }
+    }
+}
+
+impl Sources {
+    /// Shrinks an image to fit within `max_size` pixels, returning it
+    /// re-encoded as a lossless PNG, or `None` if it already fits.
+    pub fn resized(
+        &self,
+        bytes: &[u8],
+        format: image::ImageFormat,
+        max_size: u32,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let mut reader = image::io::Reader::new(io::Cursor::new(bytes));
+        reader.set_format(format);
+        let (width, height) = reader.into_dimensions()?;
+        if width.max(height) <= max_size {
+            return Ok(None);
+        }
+
+        let key = (self.hash(bytes), max_size);
+        if let Some(resized) = self.resized.borrow().get(&key) {
+            return Ok(Some(resized.clone()));
+        }
+
+        log::warn!("Image is too large! ({width}x{height}), resizing to fit {max_size}x{max_size}");
+        let mut reader = image::io::Reader::new(io::Cursor::new(bytes));
+        reader.set_format(format);
+        let image = reader
+            .decode()?
+            .resize(max_size, max_size, image::imageops::Lanczos3);
+
+        let mut output = Vec::new();
+        PngEncoder::new(&mut output).write_image(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color(),
+        )?;
+
+        let output = Rc::new(output);
+        self.resized.borrow_mut().insert(key, output.clone());
+        Ok(Some(output))
+    }
+
+    /// Decodes an image to RGBA8.
+    pub fn decoded(&self, bytes: &[u8]) -> anyhow::Result<Rc<RgbaImage>> {
+        let key = self.hash(bytes);
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        if let Some((decoded, last_used)) = self.decoded.borrow_mut().get_mut(&key) {
+            *last_used = now;
+            return Ok(decoded.clone());
+        }
+
+        let decoded = image::load_from_memory(bytes)
+            .context("failed to decode image")?
+            .into_rgba8();
+        let decoded = Rc::new(decoded);
+        let mut cache = self.decoded.borrow_mut();
+        cache.insert(key, (decoded.clone(), now));
+
+        // Drop the least recently used until it fits, keeping the newest
+        // even if it's too big on its own.
+        let size = |image: &RgbaImage| image.as_raw().len();
+        let mut total: usize = cache.values().map(|(image, _)| size(image)).sum();
+        while total > self.max_decoded_bytes && cache.len() > 1 {
+            let (&oldest, (image, _)) = cache
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .unwrap();
+            total -= size(image);
+            cache.remove(&oldest);
+        }
+
+        Ok(decoded)
+    }
+
+    /// Drops every image that hasn't been used since the last call, so
+    /// images that have since changed don't stay in memory forever.
+    pub fn forget_unused(&self) {
+        let used = self.used.take();
+        self.resized
+            .borrow_mut()
+            .retain(|(hash, _), _| used.contains(hash));
+        self.decoded
+            .borrow_mut()
+            .retain(|hash, _| used.contains(hash));
+    }
+
+    fn hash(&self, bytes: &[u8]) -> u64 {
+        let mut hasher = seahash::SeaHasher::new();
+        hasher.write(bytes);
+        let hash = hasher.finish();
+        self.used.borrow_mut().insert(hash);
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open;
+
+    #[test]
+    fn drops_least_recently_decoded() {
+        let png = |width| {
+            let mut png = Vec::new();
+            let image = RgbaImage::new(width, 1);
+            PngEncoder::new(&mut png)
+                .write_image(image.as_raw(), width, 1, image::ColorType::Rgba8)
+                .unwrap();
+            png
+        };
+        let pngs = [png(1), png(2), png(3)];
+        let sources = Sources {
+            max_decoded_bytes: 20,
+            ..Default::default()
+        };
+
+        let first = sources.decoded(&pngs[0]).unwrap();
+        sources.decoded(&pngs[1]).unwrap();
+        assert!(Rc::ptr_eq(&first, &sources.decoded(&pngs[0]).unwrap()));
+
+        // 4 + 8 + 12 bytes is too many, so the second one goes.
+        sources.decoded(&pngs[2]).unwrap();
+        let decoded = sources.decoded.borrow();
+        let mut widths: Vec<_> = decoded.values().map(|(image, _)| image.width()).collect();
+        widths.sort();
+        assert_eq!(widths, [1, 3]);
+    }
+
+    #[test]
+    fn resizes_once() {
+        let input = open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let image = input.document.images().next().unwrap();
+        let (png, _) = input.image_data(&image).unwrap();
+        let sources = Sources::default();
+
+        assert!(sources
+            .resized(&png, image::ImageFormat::Png, 256)
+            .unwrap()
+            .is_none());
+
+        let first = sources
+            .resized(&png, image::ImageFormat::Png, 128)
+            .unwrap()
+            .unwrap();
+        let second = sources
+            .resized(&png, image::ImageFormat::Png, 128)
+            .unwrap()
+            .unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(sources.decoded(&first).unwrap().dimensions(), (128, 128));
+
+        sources.forget_unused();
+        assert_eq!(sources.resized.borrow().len(), 1);
+        sources.forget_unused();
+        assert!(sources.resized.borrow().is_empty());
+        assert!(sources.decoded.borrow().is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+//! Finding `toktx` and making sure it's new enough, so a missing or old one
+//! gets a helpful error up front rather than a confusing one halfway through,
+//! and running it without hanging forever if it gets stuck.
+
+use std::{
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use wait_timeout::ChildExt;
+
+/// The oldest version of `toktx` we know works.
+const MIN_VERSION: Version = Version(4, 1, 0);
+
+/// How many times to try running `toktx` before giving up, if it fails in a
+/// way that might not happen again.
+const ATTEMPTS: u32 = 3;
+
+/// The longest `toktx --version` gets, so a wrapper that's stuck doesn't hang
+/// us before we've started.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+const INSTALL_HELP: &str = "Install KTX-Software 4.1.0 or newer from \
+    https://github.com/KhronosGroup/KTX-Software/releases and put toktx on your PATH, \
+    or point SQUISHER_TOKTX or `toktx` in squisher.toml at it";
+
+/// A `toktx` that's been checked to be new enough.
+#[derive(Debug, Clone)]
+pub struct Toktx {
+    path: PathBuf,
+    /// The version as `toktx` reported it, eg. "v4.1.0".
+    version: String,
+    /// How long `toktx` gets to compress one texture before it's killed.
+    timeout: Duration,
+}
+
+/// Why running `toktx` once failed.
+struct Failure {
+    error: anyhow::Error,
+    /// Whether trying again might work, eg. it was killed by the system
+    /// rather than rejecting the image.
+    transient: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Version(u32, u32, u32);
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl Toktx {
+    /// Finds `toktx` from the `SQUISHER_TOKTX` environment variable, the
+    /// config or the PATH, in that order, and checks its version.
+    pub fn find(configured: Option<&Path>, timeout: Duration) -> anyhow::Result<Self> {
+        let path = locate(configured)?;
+        let output = run_once(
+            Command::new(&path).arg("--version"),
+            timeout.min(VERSION_TIMEOUT),
+        )
+        .map_err(|failure| {
+            failure.error.context(format!(
+                "{} --version failed, it might be too old. {INSTALL_HELP}",
+                path.display()
+            ))
+        })?;
+        let version = String::from_utf8_lossy(&output);
+        let version = version.split_whitespace().last().unwrap_or_default();
+
+        match parse_version(version) {
+            Some(parsed) if parsed < MIN_VERSION => bail!(
+                "{} is version {parsed}, but squisher needs {MIN_VERSION} or newer. {INSTALL_HELP}",
+                path.display()
+            ),
+            Some(_) => log::debug!("Using toktx {version} from {}", path.display()),
+            None => log::warn!(
+                "Unable to tell what version {} is from {version:?}, assuming it's new enough",
+                path.display()
+            ),
+        }
+
+        Ok(Self {
+            path,
+            version: version.to_string(),
+            timeout,
+        })
+    }
+
+    /// Whether `find` would pick this `toktx` for the given config, so it
+    /// doesn't need checking again.
+    pub fn is_found_from(&self, configured: Option<&Path>) -> bool {
+        locate(configured).map_or(false, |path| path == self.path)
+    }
+
+    /// The same `toktx`, given a different amount of time per texture.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            timeout,
+            ..self.clone()
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn command(&self) -> Command {
+        Command::new(&self.path)
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Runs a `toktx` command, returning what it wrote to stdout. Failures
+    /// that might be transient are retried, and errors include what it wrote
+    /// to stderr.
+    pub fn run(&self, command: &mut Command) -> anyhow::Result<Vec<u8>> {
+        let mut attempt = 1;
+        loop {
+            match run_once(command, self.timeout) {
+                Ok(output) => return Ok(output),
+                Err(failure) if failure.transient && attempt < ATTEMPTS => {
+                    log::warn!("toktx failed, trying again: {:#}", failure.error);
+                    thread::sleep(Duration::from_millis(500) * attempt);
+                    attempt += 1;
+                }
+                Err(failure) => return Err(failure.error),
+            }
+        }
+    }
+}
+
+/// Works out which `toktx` to use, without running it.
+fn locate(configured: Option<&Path>) -> anyhow::Result<PathBuf> {
+    match std::env::var_os("SQUISHER_TOKTX").filter(|path| !path.is_empty()) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => match configured {
+            Some(path) => Ok(path.to_path_buf()),
+            None => search_path("toktx")
+                .with_context(|| format!("toktx isn't on your PATH. {INSTALL_HELP}")),
+        },
+    }
+}
+
+fn run_once(command: &mut Command, timeout: Duration) -> Result<Vec<u8>, Failure> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Failure {
+            transient: is_transient(&e),
+            error: anyhow::Error::new(e).context("failed to start toktx"),
+        })?;
+
+    // Read both pipes at once, so toktx never blocks on a full pipe that
+    // we're not reading.
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let status = match child.wait_timeout(timeout) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Failure {
+                error: anyhow!("toktx took longer than {timeout:?} and was killed"),
+                transient: false,
+            });
+        }
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Failure {
+                transient: is_transient(&e),
+                error: anyhow::Error::new(e).context("failed to wait for toktx"),
+            });
+        }
+    };
+
+    let join = |reader: thread::JoinHandle<io::Result<Vec<u8>>>| {
+        reader
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "reader panicked")))
+    };
+    let stdout = join(stdout);
+    let stderr = join(stderr).unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr);
+    let stderr = stderr.trim();
+
+    if !status.success() {
+        return Err(Failure {
+            error: anyhow!("toktx failed ({status}): {stderr}"),
+            transient: was_killed(status),
+        });
+    }
+    if !stderr.is_empty() {
+        log::debug!("toktx said: {stderr}");
+    }
+
+    stdout.map_err(|e| Failure {
+        error: anyhow::Error::new(e).context("failed to read toktx output"),
+        transient: false,
+    })
+}
+
+/// Whether a process was killed from outside, eg. by the system running out
+/// of memory, rather than giving up on or crashing on the image.
+#[cfg(unix)]
+fn was_killed(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+
+    const SIGKILL: i32 = 9;
+    const SIGTERM: i32 = 15;
+    matches!(status.signal(), Some(SIGKILL | SIGTERM))
+}
+
+#[cfg(not(unix))]
+fn was_killed(_: ExitStatus) -> bool {
+    false
+}
+
+fn read_in_background(
+    pipe: Option<impl Read + Send + 'static>,
+) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut output)?;
+        }
+        Ok(output)
+    })
+}
+
+/// Whether an error starting or waiting for a process might go away if we
+/// try again.
+fn is_transient(error: &io::Error) -> bool {
+    // ETXTBSY, which happens when another thread has just written the file
+    // and doesn't have its own error kind.
+    const TEXT_FILE_BUSY: i32 = 26;
+
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    ) || (cfg!(unix) && error.raw_os_error() == Some(TEXT_FILE_BUSY))
+}
+
+/// Finds an executable in the directories on the PATH.
+fn search_path(name: &str) -> Option<PathBuf> {
+    let file_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+}
+
+/// Parses versions like "v4.1.0", "v4.3.0-rc1" or "v4.0.__default__".
+fn parse_version(version: &str) -> Option<Version> {
+    let mut parts = version
+        .strip_prefix('v')
+        .unwrap_or(version)
+        .split('.')
+        .map(|part| {
+            let digits = part
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(part.len());
+            part[..digits].parse().ok()
+        });
+
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some(Version(major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(parse_version("v4.1.0"), Some(Version(4, 1, 0)));
+        assert_eq!(parse_version("v4.3.0-rc1"), Some(Version(4, 3, 0)));
+        assert_eq!(parse_version("v4.0.__default__"), Some(Version(4, 0, 0)));
+        assert_eq!(parse_version("4.2"), Some(Version(4, 2, 0)));
+        assert_eq!(parse_version("unknown"), None);
+        assert!(parse_version("v4.0.0").unwrap() < MIN_VERSION);
+        assert!(parse_version("v4.10.1").unwrap() > MIN_VERSION);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reject_old_toktx() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("toktx");
+        fs_err::write(&path, "#!/bin/sh\necho 'toktx v4.0.0'\n").unwrap();
+        fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let error = Toktx::find(Some(&path), Duration::from_secs(10))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("needs 4.1.0 or newer"), "{error}");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_commands() {
+        let toktx = Toktx {
+            path: "sh".into(),
+            version: "v4.1.0".into(),
+            timeout: Duration::from_millis(500),
+        };
+        let sh = |script: &str| {
+            let mut command = toktx.command();
+            command.args(["-c", script]);
+            toktx.run(&mut command)
+        };
+
+        // Enough on both pipes to fill them if they weren't read together.
+        let output = sh("head -c 1000000 /dev/zero >&2; head -c 1000000 /dev/zero").unwrap();
+        assert_eq!(output.len(), 1_000_000);
+
+        let error = format!("{:#}", sh("echo 'bad image' >&2; exit 1").unwrap_err());
+        assert!(error.contains("bad image"), "{error}");
+
+        let error = format!("{:#}", sh("sleep 5").unwrap_err());
+        assert!(error.contains("was killed"), "{error}");
+
+        // Crashing isn't worth trying again, but being killed is.
+        let start = std::time::Instant::now();
+        assert!(sh("kill -SEGV $$").is_err());
+        assert!(start.elapsed() < Duration::from_millis(400));
+        let start = std::time::Instant::now();
+        assert!(sh("kill -TERM $$").is_err());
+        assert!(start.elapsed() >= Duration::from_millis(1500));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn stuck_version() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("toktx");
+        fs_err::write(
+            &path,
+            "#!/bin/sh
+read answer
+sleep 5
+",
+        )
+        .unwrap();
+        fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let start = std::time::Instant::now();
+        let error = format!(
+            "{:#}",
+            Toktx::find(Some(&path), Duration::from_millis(500)).unwrap_err()
+        );
+        assert!(error.contains("was killed"), "{error}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
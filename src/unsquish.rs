@@ -0,0 +1,163 @@
+//! The `unsquish` subcommand, which turns a squished file back into one that
+//! any glTF viewer can open, with PNG images.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{bail, Context};
+use image::{codecs::png::PngEncoder, ImageEncoder, RgbaImage};
+
+use crate::{open, pack_buffers, quality, to_glb, Input};
+
+#[derive(clap::Args)]
+pub struct UnsquishArgs {
+    /// The path to the squished file.
+    input: PathBuf,
+
+    /// Where to write the result. A `.gltf` output gets its buffer written
+    /// to a `.bin` file next to it, anything else is written as a GLB.
+    output: PathBuf,
+
+    /// Enables more verbose logging.
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+pub fn unsquish(args: UnsquishArgs) -> anyhow::Result<()> {
+    crate::configure_logging(args.verbose);
+
+    log::info!("Unsquishing {}", args.input.display());
+    let input = open(&args.input)?;
+    let pngs = decode_images(&input)?;
+
+    let images = pngs
+        .iter()
+        .map(|(index, png)| (*index, (png.as_slice(), "image/png")))
+        .collect();
+    let (mut root, blob) = pack_buffers(input, &images)?;
+
+    if args.output.extension().and_then(|s| s.to_str()) == Some("gltf") {
+        let bin = args.output.with_extension("bin");
+        let bin_name = bin
+            .file_name()
+            .and_then(|s| s.to_str())
+            .context("output path is not valid UTF-8")?;
+        root.buffers[0].uri = Some(bin_name.into());
+
+        fs_err::write(&bin, blob)?;
+        fs_err::write(
+            &args.output,
+            gltf::json::serialize::to_string_pretty(&root)?,
+        )?;
+    } else {
+        fs_err::write(&args.output, to_glb(&root, blob)?)?;
+    }
+
+    log::info!("Unsquished file: {}", args.output.display());
+    Ok(())
+}
+
+/// Decodes every KTX2 image in the document and re-encodes it as a PNG,
+/// returning the PNGs by image index.
+fn decode_images(input: &Input) -> anyhow::Result<HashMap<usize, Vec<u8>>> {
+    let normal_maps = normal_map_images(&input.document);
+
+    let mut pngs = HashMap::new();
+    for image in input.document.images() {
+        let (bytes, mime_type) = input.image_data(&image)?;
+        if mime_type != Some("image/ktx2") {
+            continue;
+        }
+
+        let index = image.index();
+        let png = ktx2_to_png(&bytes, normal_maps[index])
+            .with_context(|| format!("failed to decode image {index}"))?;
+        log::info!("Decoded image {index}");
+        pngs.insert(index, png);
+    }
+
+    if pngs.is_empty() {
+        bail!("there are no KTX2 images to decode");
+    }
+
+    Ok(pngs)
+}
+
+/// Whether each image in the document is used as a normal map.
+pub(crate) fn normal_map_images(document: &gltf::Document) -> Vec<bool> {
+    let mut normal_maps = vec![false; document.images().len()];
+    for material in document.materials() {
+        if let Some(normal) = material.normal_texture() {
+            normal_maps[normal.texture().source().index()] = true;
+        }
+    }
+    normal_maps
+}
+
+/// Decodes the largest mip level of a KTX2 image into a PNG.
+pub fn ktx2_to_png(bytes: &[u8], normal_map: bool) -> anyhow::Result<Vec<u8>> {
+    let mut decoded = quality::decode_ktx2(bytes)?;
+
+    // Squished normal maps have X in every colour channel and Y in alpha.
+    // Only unpack ones that look like that, in case the file came from
+    // somewhere else.
+    if normal_map && decoded.pixels().all(|p| p[0] == p[1] && p[1] == p[2]) {
+        quality::unpack_normal_map(&mut decoded);
+    }
+
+    encode_png(&decoded)
+}
+
+fn encode_png(image: &RgbaImage) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image::ColorType::Rgba8,
+    )?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality::tests::rgba8_ktx2;
+
+    /// Returns the bytes and MIME type of the first image.
+    fn first_image(input: &Input) -> (&[u8], &str) {
+        match input.document.images().next().unwrap().source() {
+            gltf::image::Source::View { view, mime_type } => (input.view_data(&view), mime_type),
+            gltf::image::Source::Uri { .. } => panic!("image should be in a buffer view"),
+        }
+    }
+
+    #[test]
+    fn ktx2_to_png() {
+        // Make a squished file without needing `toktx`.
+        let input = open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let original = image::load_from_memory(first_image(&input).0)
+            .unwrap()
+            .into_rgba8();
+        let ktx2 = rgba8_ktx2(&original);
+        let images = HashMap::from([(0, (ktx2.as_slice(), "image/ktx2"))]);
+        let (root, blob) = pack_buffers(input, &images).unwrap();
+
+        fs_err::create_dir_all("test_output").unwrap();
+        let squished = "test_output/BoxTexturedBinary_rgba8_only.glb";
+        fs_err::write(squished, to_glb(&root, blob).unwrap()).unwrap();
+
+        let output = "test_output/BoxTexturedBinary_unsquished.gltf";
+        unsquish(UnsquishArgs {
+            input: squished.into(),
+            output: output.into(),
+            verbose: false,
+        })
+        .unwrap();
+
+        let output = open(output.as_ref()).unwrap();
+        let (png, mime_type) = first_image(&output);
+        assert_eq!(mime_type, "image/png");
+        let decoded = image::load_from_memory(png).unwrap();
+        assert_eq!(decoded.into_rgba8(), original);
+    }
+}
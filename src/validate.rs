@@ -0,0 +1,441 @@
+//! Checks that the document we're about to write is one that loaders will
+//! accept, so that problems show up here rather than in a headset.
+//!
+//! The output deliberately breaks the glTF spec by embedding `image/ktx2`
+//! images without an extension, so that isn't reported.
+
+use std::fmt;
+
+use gltf::json::{
+    validation::{Checked, Validate},
+    Root,
+};
+
+/// A problem with the output document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// A JSON pointer to the part of the document with the problem.
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.pointer, self.message)
+    }
+}
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_MAGIC: &[u8] = b"\xff\xd8\xff";
+const KTX2_MAGIC: &[u8] = b"\xabKTX 20\xbb\r\n\x1a\n";
+
+/// Validates a document whose only buffer is stored in `blob`, returning
+/// every problem found.
+pub fn validate(root: &Root, blob: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut report = |pointer: String, message: String| issues.push(Issue { pointer, message });
+
+    // Start with the checks that the gltf crate can do for us: mostly index
+    // bounds and enum values.
+    root.validate(root, gltf::json::Path::new, &mut |path, error| {
+        report(json_pointer(path().as_str()), error.to_string())
+    });
+
+    for (index, buffer) in root.buffers.iter().enumerate() {
+        if buffer.uri.is_some() {
+            report(
+                format!("/buffers/{index}/uri"),
+                "buffers must be stored in the GLB".into(),
+            );
+        } else if buffer.byte_length as usize > blob.len() {
+            report(
+                format!("/buffers/{index}/byteLength"),
+                format!(
+                    "buffer is {} bytes long, but the BIN chunk is only {} bytes",
+                    buffer.byte_length,
+                    blob.len()
+                ),
+            );
+        }
+    }
+
+    for (index, view) in root.buffer_views.iter().enumerate() {
+        let pointer = format!("/bufferViews/{index}");
+        let end = view.byte_offset.unwrap_or_default() as u64 + view.byte_length as u64;
+        if let Some(buffer) = root.buffers.get(view.buffer.value()) {
+            if end > buffer.byte_length as u64 {
+                report(
+                    format!("{pointer}/byteLength"),
+                    format!(
+                        "view ends at byte {end}, past the end of buffer {} ({} bytes)",
+                        view.buffer.value(),
+                        buffer.byte_length
+                    ),
+                );
+            }
+        }
+
+        if let Some(stride) = view.byte_stride {
+            if !(4..=252).contains(&stride) || stride % 4 != 0 {
+                report(
+                    format!("{pointer}/byteStride"),
+                    format!("stride {stride} must be a multiple of 4 between 4 and 252"),
+                );
+            }
+        }
+    }
+
+    for (index, accessor) in root.accessors.iter().enumerate() {
+        validate_accessor(root, index, accessor, &mut report);
+    }
+
+    for extension in &root.extensions_required {
+        if !root.extensions_used.contains(extension) {
+            report(
+                "/extensionsRequired".into(),
+                format!("{extension} is required but not listed in extensionsUsed"),
+            );
+        }
+    }
+
+    let uses_lights = root
+        .extensions
+        .as_ref()
+        .map_or(false, |e| e.khr_lights_punctual.is_some())
+        || root.nodes.iter().any(|node| {
+            node.extensions
+                .as_ref()
+                .map_or(false, |e| e.khr_lights_punctual.is_some())
+        });
+    if uses_lights
+        && !root
+            .extensions_used
+            .iter()
+            .any(|e| e == "KHR_lights_punctual")
+    {
+        report(
+            "/extensionsUsed".into(),
+            "KHR_lights_punctual is used but not listed".into(),
+        );
+    }
+
+    for (index, image) in root.images.iter().enumerate() {
+        validate_image(root, blob, index, image, &mut report);
+    }
+
+    issues
+}
+
+fn validate_accessor(
+    root: &Root,
+    index: usize,
+    accessor: &gltf::json::Accessor,
+    report: &mut impl FnMut(String, String),
+) {
+    let pointer = format!("/accessors/{index}");
+    if accessor.count == 0 {
+        report(
+            format!("{pointer}/count"),
+            "count must be at least 1".into(),
+        );
+    }
+
+    let (Checked::Valid(component_type), Checked::Valid(type_)) =
+        (&accessor.component_type, &accessor.type_)
+    else {
+        // Already reported by the gltf crate.
+        return;
+    };
+    let component_size = component_type.0.size();
+    let element_size = component_size * type_.multiplicity();
+
+    if let Some(view) = accessor
+        .buffer_view
+        .and_then(|v| root.buffer_views.get(v.value()))
+    {
+        let stride = view.byte_stride.map_or(element_size, |s| s as usize);
+        if stride < element_size {
+            report(
+                format!("{pointer}/bufferView"),
+                format!("view stride {stride} is smaller than the element size {element_size}"),
+            );
+        }
+        if stride % component_size != 0 {
+            report(
+                format!("{pointer}/bufferView"),
+                format!(
+                    "view stride {stride} is not a multiple of the component size {component_size}"
+                ),
+            );
+        }
+
+        let offset = view.byte_offset.unwrap_or_default() as usize + accessor.byte_offset as usize;
+        if offset % component_size != 0 {
+            report(
+                format!("{pointer}/byteOffset"),
+                format!("offset {offset} into the buffer is not aligned to the component size {component_size}"),
+            );
+        }
+
+        let count = accessor.count.max(1) as usize;
+        let end = accessor.byte_offset as usize + stride * (count - 1) + element_size;
+        if end > view.byte_length as usize {
+            report(
+                format!("{pointer}/count"),
+                format!(
+                    "accessor needs {end} bytes, but buffer view {} is only {} bytes",
+                    accessor.buffer_view.unwrap().value(),
+                    view.byte_length
+                ),
+            );
+        }
+    }
+
+    if let Some(sparse) = &accessor.sparse {
+        let pointer = format!("{pointer}/sparse");
+        let index_size = match &sparse.indices.component_type {
+            Checked::Valid(t) => t.0.size(),
+            Checked::Invalid => return,
+        };
+
+        for (field, view, offset, size) in [
+            (
+                "indices",
+                sparse.indices.buffer_view,
+                sparse.indices.byte_offset,
+                index_size,
+            ),
+            (
+                "values",
+                sparse.values.buffer_view,
+                sparse.values.byte_offset,
+                element_size,
+            ),
+        ] {
+            let Some(view) = root.buffer_views.get(view.value()) else {
+                continue;
+            };
+            let end = offset as usize + size * sparse.count as usize;
+            if end > view.byte_length as usize {
+                report(
+                    format!("{pointer}/{field}/byteOffset"),
+                    format!(
+                        "sparse {field} need {end} bytes, but the buffer view is only {} bytes",
+                        view.byte_length
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn validate_image(
+    root: &Root,
+    blob: &[u8],
+    index: usize,
+    image: &gltf::json::Image,
+    report: &mut impl FnMut(String, String),
+) {
+    let pointer = format!("/images/{index}");
+    let view = match (image.buffer_view, &image.uri) {
+        (Some(_), Some(_)) => {
+            report(pointer, "image has both a uri and a bufferView".into());
+            return;
+        }
+        (None, None) => {
+            report(pointer, "image has neither a uri nor a bufferView".into());
+            return;
+        }
+        (None, Some(_)) => return,
+        (Some(view), None) => view,
+    };
+
+    let Some(mime_type) = &image.mime_type else {
+        report(
+            format!("{pointer}/mimeType"),
+            "images stored in a bufferView must have a mimeType".into(),
+        );
+        return;
+    };
+
+    let Some(bytes) = root.buffer_views.get(view.value()).and_then(|view| {
+        let start = view.byte_offset.unwrap_or_default() as usize;
+        blob.get(start..start + view.byte_length as usize)
+    }) else {
+        // Out of bounds views have already been reported.
+        return;
+    };
+
+    let magic = match mime_type.0.as_str() {
+        "image/png" => PNG_MAGIC,
+        "image/jpeg" => JPEG_MAGIC,
+        "image/ktx2" => KTX2_MAGIC,
+        other => {
+            report(
+                format!("{pointer}/mimeType"),
+                format!("unsupported MIME type {other}"),
+            );
+            return;
+        }
+    };
+
+    if !bytes.starts_with(magic) {
+        report(
+            format!("{pointer}/mimeType"),
+            format!("image data does not look like {}", mime_type.0),
+        );
+        return;
+    }
+
+    if magic == KTX2_MAGIC {
+        if let Err(message) = validate_ktx2(bytes) {
+            report(format!("{pointer}/bufferView"), message);
+        }
+    }
+}
+
+/// Checks that the header of a KTX2 file describes a usable 2D texture.
+fn validate_ktx2(bytes: &[u8]) -> Result<(), String> {
+    let reader = crate::quality::read_ktx2(bytes).map_err(|e| format!("{e:#}"))?;
+    let header = reader.header();
+
+    if header.format.is_none()
+        && header.supercompression_scheme != Some(ktx2::SupercompressionScheme::BasisLZ)
+    {
+        return Err("KTX2 file has an undefined VkFormat".into());
+    }
+    if header.pixel_height == 0 || header.pixel_depth != 0 {
+        return Err(format!(
+            "KTX2 file is {}x{}x{}, but should be a 2D texture",
+            header.pixel_width, header.pixel_height, header.pixel_depth
+        ));
+    }
+    if header.face_count != 1 || header.layer_count > 1 {
+        return Err("KTX2 cubemaps and arrays are not supported".into());
+    }
+
+    let max_levels = 32 - header.pixel_width.max(header.pixel_height).leading_zeros();
+    if header.level_count > max_levels {
+        return Err(format!(
+            "KTX2 file has {} mip levels, but a {}x{} image can have at most {max_levels}",
+            header.level_count, header.pixel_width, header.pixel_height
+        ));
+    }
+
+    Ok(())
+}
+
+/// Converts a path from the gltf crate, like `meshes[0].primitives[1]["POSITION"]`,
+/// into a JSON pointer, like `/meshes/0/primitives/1/POSITION`.
+fn json_pointer(path: &str) -> String {
+    let mut pointer = String::new();
+    let mut token = String::new();
+    let mut chars = path.chars();
+
+    let flush = |token: &mut String, pointer: &mut String| {
+        if !token.is_empty() {
+            pointer.push('/');
+            pointer.push_str(&token.replace('~', "~0").replace('/', "~1"));
+            token.clear();
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '.' | '[' | ']' => flush(&mut token, &mut pointer),
+            '"' => {
+                // Quoted keys may contain any of the characters above.
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    flush(&mut token, &mut pointer);
+
+    pointer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::json::{image::MimeType, Index};
+
+    fn box_textured_binary() -> (Root, Vec<u8>) {
+        let input = crate::open("test_data/BoxTexturedBinary.glb".as_ref()).unwrap();
+        let blob = input.buffers.into_iter().next().unwrap();
+        (input.document.into_json(), blob)
+    }
+
+    fn pointers(issues: &[Issue]) -> Vec<&str> {
+        issues.iter().map(|i| i.pointer.as_str()).collect()
+    }
+
+    #[test]
+    fn valid_document() {
+        let (root, blob) = box_textured_binary();
+        assert_eq!(validate(&root, &blob), vec![]);
+    }
+
+    #[test]
+    fn index_out_of_bounds() {
+        let (mut root, blob) = box_textured_binary();
+        root.textures[0].source = Index::new(7);
+
+        let issues = validate(&root, &blob);
+        assert_eq!(pointers(&issues), ["/textures/0/source"]);
+    }
+
+    #[test]
+    fn accessor_out_of_bounds() {
+        let (mut root, blob) = box_textured_binary();
+        root.accessors[0].count += 1;
+
+        let issues = validate(&root, &blob);
+        assert_eq!(pointers(&issues), ["/accessors/0/count"]);
+    }
+
+    #[test]
+    fn misaligned_accessor() {
+        let (mut root, blob) = box_textured_binary();
+        root.accessors[1].byte_offset = 2;
+
+        let issues = validate(&root, &blob);
+        assert_eq!(pointers(&issues), ["/accessors/1/byteOffset"]);
+    }
+
+    #[test]
+    fn mismatched_mime_type() {
+        let (mut root, blob) = box_textured_binary();
+        root.images[0].mime_type = Some(MimeType("image/ktx2".into()));
+
+        let issues = validate(&root, &blob);
+        assert_eq!(pointers(&issues), ["/images/0/mimeType"]);
+    }
+
+    #[test]
+    fn required_extension_not_used() {
+        let (mut root, blob) = box_textured_binary();
+        root.extensions_required.push("KHR_texture_basisu".into());
+
+        let issues = validate(&root, &blob);
+        assert_eq!(pointers(&issues), ["/extensionsRequired"]);
+    }
+
+    #[test]
+    fn pointers_from_paths() {
+        assert_eq!(
+            json_pointer("accessors[0].bufferView"),
+            "/accessors/0/bufferView"
+        );
+        assert_eq!(
+            json_pointer("meshes[0].primitives[1].attributes[\"TEXCOORD_0\"]"),
+            "/meshes/0/primitives/1/attributes/TEXCOORD_0"
+        );
+        assert_eq!(json_pointer("extras[\"a/b~c\"]"), "/extras/a~1b~0c");
+    }
+}
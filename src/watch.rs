@@ -0,0 +1,100 @@
+//! `--watch`, which squishes the input again every time it's exported, so
+//! artists don't have to re-run squisher by hand.
+
+use std::{
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{deps, open, overrides, Args};
+
+/// How often to check the watched files for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Runs `squish`, then runs it again every time one of the files it depends
+/// on changes, until the process is stopped. Failures are logged rather than
+/// ending the watch, as the next export might well fix them.
+pub(crate) fn watch(
+    args: &Args,
+    mut squish: impl FnMut() -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    loop {
+        if let Err(err) = squish() {
+            log::error!("Failed to squish: {err:?}");
+        }
+
+        let files = watched_files(args);
+        log::info!(
+            "Watching {} file(s) for changes, press Ctrl+C to stop",
+            files.len()
+        );
+        wait_for_change(&files);
+        log::info!("Change detected, squishing again");
+    }
+}
+
+/// The input, any buffers and images it refers to by URI, its texture
+/// overrides and its config.
+fn watched_files(args: &Args) -> Vec<PathBuf> {
+    match open(&args.input) {
+        Ok(input) => deps::dependencies(args, &input),
+        // If the input can't be opened (say it's half written) we'll still
+        // notice when it's written again.
+        Err(_) => vec![args.input.clone(), overrides::Sidecar::path(&args.input)],
+    }
+}
+
+/// Blocks until any of the files are created, modified or deleted, and then
+/// until they've stopped changing, so we don't read a half-written export.
+fn wait_for_change(files: &[PathBuf]) {
+    let before = modified_times(files);
+    let mut current = before.clone();
+    while current == before {
+        thread::sleep(POLL_INTERVAL);
+        current = modified_times(files);
+    }
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let latest = modified_times(files);
+        if latest == current {
+            break;
+        }
+        current = latest;
+    }
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| file.metadata().and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn watch_external_files() {
+        let cli = crate::Cli::try_parse_from([
+            "squisher",
+            "--watch",
+            "test_data/BoxTexturedMultiBuffer.gltf",
+            "output.glb",
+        ])
+        .unwrap();
+        let args = cli.squish.unwrap();
+        assert!(args.watch);
+
+        let files = watched_files(&args);
+        assert!(files.contains(&PathBuf::from("test_data/BoxTexturedMultiBuffer.gltf")));
+        assert!(files.contains(&PathBuf::from(
+            "test_data/BoxTexturedMultiBuffer.squisher.toml"
+        )));
+        assert!(files.contains(&PathBuf::from("test_data/BoxTexturedMultiBuffer.bin")));
+        assert!(!files.iter().any(|file| file.starts_with("data:")));
+    }
+}